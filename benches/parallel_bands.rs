@@ -0,0 +1,28 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use image::Rgb;
+use rayon::ThreadPoolBuilder;
+use rsixel::{open_image, EncoderBuilder, OctreeQuantizer};
+use std::{io, path::Path, thread};
+
+fn criterion_benchmark(c: &mut Criterion) {
+    let img = open_image(Path::new("assets/snake.png"), Rgb([0, 0, 0])).unwrap();
+    let max_threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let mut group = c.benchmark_group("encode snake.png");
+    for threads in [1, 2, 4, 8, 16].into_iter().filter(|&n| n <= max_threads) {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+        let mut encoder = EncoderBuilder::<OctreeQuantizer>::new().build();
+        group.bench_with_input(BenchmarkId::from_parameter(threads), &threads, |b, _| {
+            b.iter(|| {
+                pool.install(|| encoder.encode(&mut io::sink(), &img))
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
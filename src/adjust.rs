@@ -0,0 +1,193 @@
+use image::{Pixel, Rgb, RgbImage};
+use std::borrow::Cow;
+
+/// Share of the darkest and brightest values clipped by [`AutoLevels::Stretch`].
+const STRETCH_CLIP: f32 = 0.005;
+
+/// Automatic tone correction computed from the image histogram.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum AutoLevels {
+    /// Stretch the value range to black and white, ignoring outliers
+    Stretch,
+    /// Spread values evenly with histogram equalization
+    Equalize,
+}
+
+/// Color corrections applied to an image before it is quantized. Auto levels
+/// run first, then brightness, contrast and gamma, and saturation last.
+#[derive(Debug, Clone, Copy)]
+pub struct Adjustments {
+    /// Added to every channel, `-1.0` turns the image black and `1.0` white.
+    pub brightness: f32,
+    /// Scales values away from mid gray, `1.0` keeps the image unchanged.
+    pub contrast: f32,
+    /// Values above `1.0` brighten mid tones, below `1.0` darken them.
+    pub gamma: f32,
+    /// `0.0` produces a grayscale image, values above `1.0` boost colors.
+    pub saturation: f32,
+    pub auto_levels: Option<AutoLevels>,
+}
+
+impl Default for Adjustments {
+    fn default() -> Self {
+        Self {
+            brightness: 0.0,
+            contrast: 1.0,
+            gamma: 1.0,
+            saturation: 1.0,
+            auto_levels: None,
+        }
+    }
+}
+
+impl Adjustments {
+    fn is_identity(&self) -> bool {
+        self.brightness == 0.0
+            && self.contrast == 1.0
+            && self.gamma == 1.0
+            && self.saturation == 1.0
+            && self.auto_levels.is_none()
+    }
+
+    /// Lookup table of the auto levels, brightness, contrast and gamma steps.
+    fn table(&self, img: &RgbImage) -> [u8; 256] {
+        let levels = match self.auto_levels {
+            Some(AutoLevels::Stretch) => stretch_table(img),
+            Some(AutoLevels::Equalize) => equalize_table(img),
+            None => std::array::from_fn(|i| i as u8),
+        };
+        let exponent = 1.0 / self.gamma.max(0.01);
+        levels.map(|value| {
+            let value = value as f32 / 255.0;
+            let value = (value - 0.5) * self.contrast.max(0.0) + 0.5 + self.brightness;
+            (value.clamp(0.0, 1.0).powf(exponent) * 255.0).round() as u8
+        })
+    }
+
+    pub fn apply<'a>(&self, mut img: Cow<'a, RgbImage>) -> Cow<'a, RgbImage> {
+        if self.is_identity() {
+            return img;
+        }
+        let table = self.table(&img);
+        let saturation = self.saturation.max(0.0);
+        for pixel in img.to_mut().pixels_mut() {
+            let Rgb([r, g, b]) = pixel.map(|c| table[c as usize]);
+            *pixel = if saturation == 1.0 {
+                Rgb([r, g, b])
+            } else {
+                let luma = 0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32;
+                Rgb([r, g, b].map(|c| {
+                    (luma + (c as f32 - luma) * saturation)
+                        .clamp(0.0, 255.0)
+                        .round() as u8
+                }))
+            };
+        }
+        img
+    }
+}
+
+/// Count of every channel value over all pixels.
+fn histogram(img: &RgbImage) -> [usize; 256] {
+    let mut histogram = [0; 256];
+    for &value in img.as_raw() {
+        histogram[value as usize] += 1;
+    }
+    histogram
+}
+
+fn stretch_table(img: &RgbImage) -> [u8; 256] {
+    let histogram = histogram(img);
+    let clip = (img.as_raw().len() as f32 * STRETCH_CLIP) as usize;
+    let mut sum = 0;
+    let low = histogram
+        .iter()
+        .position(|&count| {
+            sum += count;
+            sum > clip
+        })
+        .unwrap_or(0);
+    sum = 0;
+    let high = 255
+        - histogram
+            .iter()
+            .rev()
+            .position(|&count| {
+                sum += count;
+                sum > clip
+            })
+            .unwrap_or(0);
+    if high <= low {
+        return std::array::from_fn(|i| i as u8);
+    }
+    std::array::from_fn(|i| ((i.clamp(low, high) - low) * 255 / (high - low)) as u8)
+}
+
+fn equalize_table(img: &RgbImage) -> [u8; 256] {
+    let histogram = histogram(img);
+    let total = img.as_raw().len();
+    let first = histogram
+        .iter()
+        .copied()
+        .find(|&count| count > 0)
+        .unwrap_or(0);
+    if total == first {
+        return std::array::from_fn(|i| i as u8);
+    }
+    let mut cdf = 0;
+    histogram.map(|count| {
+        cdf += count;
+        (cdf.saturating_sub(first) * 255 / (total - first)) as u8
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_adjustments() {
+        let img = RgbImage::from_fn(4, 1, |x, _| Rgb([64 + x as u8 * 32, 128, 128]));
+        let adjust = |adjustments: Adjustments| adjustments.apply(Cow::Borrowed(&img));
+        assert!(matches!(adjust(Adjustments::default()), Cow::Borrowed(_)));
+
+        let brighter = adjust(Adjustments {
+            brightness: 0.25,
+            ..Default::default()
+        });
+        assert_eq!(*brighter.get_pixel(0, 0), Rgb([128, 192, 192]));
+
+        let gray = adjust(Adjustments {
+            saturation: 0.0,
+            ..Default::default()
+        });
+        assert!(gray.pixels().all(|Rgb([r, g, b])| r == g && g == b));
+
+        let flat = adjust(Adjustments {
+            contrast: 0.0,
+            ..Default::default()
+        });
+        assert!(flat.pixels().all(|&pixel| pixel == Rgb([128; 3])));
+
+        let gamma = adjust(Adjustments {
+            gamma: 2.0,
+            ..Default::default()
+        });
+        assert!(gamma.get_pixel(0, 0)[1] > 128);
+
+        let stretched = adjust(Adjustments {
+            auto_levels: Some(AutoLevels::Stretch),
+            ..Default::default()
+        });
+        assert_eq!(stretched.get_pixel(0, 0)[0], 0);
+        assert_eq!(stretched.get_pixel(3, 0)[0], 255);
+
+        let equalized = adjust(Adjustments {
+            auto_levels: Some(AutoLevels::Equalize),
+            ..Default::default()
+        });
+        assert_eq!(equalized.get_pixel(0, 0)[0], 0);
+        assert_eq!(equalized.get_pixel(3, 0)[0], 255);
+    }
+}
@@ -0,0 +1,205 @@
+use anyhow::Result;
+use image::{
+    imageops::{self, dither, ColorMap, FilterType},
+    GrayImage, Rgb, RgbImage,
+};
+use std::{borrow::Cow, io::Write};
+
+use crate::{Adjustments, Backend, Palette, MAX_HIGH_COLORS};
+
+/// Characters from dark to bright.
+const DEFAULT_RAMP: &str = " .:-=+*#%@";
+
+/// Minimum Sobel gradient magnitude drawn as an edge.
+const EDGE_THRESHOLD: f32 = 384.0;
+
+/// Plain text backend mapping luminance to a character ramp, for logs and
+/// other output where escape codes are not allowed.
+#[derive(Debug)]
+pub struct AsciiEncoder {
+    ramp: Vec<char>,
+    columns: Option<u32>,
+    is_dither: bool,
+    is_edges: bool,
+    adjustments: Adjustments,
+}
+
+impl Default for AsciiEncoder {
+    fn default() -> Self {
+        Self {
+            ramp: DEFAULT_RAMP.chars().collect(),
+            columns: None,
+            is_dither: false,
+            is_edges: false,
+            adjustments: Adjustments::default(),
+        }
+    }
+}
+
+impl AsciiEncoder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Characters ordered from dark to bright, an empty ramp keeps the
+    /// default one.
+    pub fn ramp(mut self, ramp: &str) -> Self {
+        if !ramp.is_empty() {
+            self.ramp = ramp.chars().take(MAX_HIGH_COLORS).collect();
+        }
+        self
+    }
+
+    /// Scale the image down to at most this many characters wide.
+    pub fn columns(mut self, columns: u32) -> Self {
+        self.columns = Some(columns.max(1));
+        self
+    }
+
+    /// Dither the luminance to the ramp levels.
+    pub fn dither(mut self, is_dither: bool) -> Self {
+        self.is_dither = is_dither;
+        self
+    }
+
+    /// Draw strong edges with `-`, `/`, `|` and `\` following their direction.
+    pub fn edges(mut self, is_edges: bool) -> Self {
+        self.is_edges = is_edges;
+        self
+    }
+
+    /// Correct the image before it is converted to luminance, e.g. stretch
+    /// the levels of low contrast images to use the whole ramp.
+    pub fn adjustments(mut self, adjustments: Adjustments) -> Self {
+        self.adjustments = adjustments;
+        self
+    }
+
+    /// Grayscale image with one pixel per character, characters are about
+    /// twice as high as wide.
+    fn luma(&self, img: &RgbImage) -> GrayImage {
+        let mut width = img.width();
+        let mut height = img.height().div_ceil(2);
+        if let Some(columns) = self.columns {
+            if width > columns {
+                height = (height * columns).div_ceil(width);
+                width = columns;
+            }
+        }
+        let luma = imageops::grayscale(img);
+        if (width, height) == luma.dimensions() {
+            luma
+        } else {
+            imageops::resize(&luma, width, height.max(1), FilterType::Triangle)
+        }
+    }
+
+    /// Ramp index of every pixel.
+    fn levels(&self, luma: &GrayImage) -> Vec<usize> {
+        let max_level = self.ramp.len() - 1;
+        if !self.is_dither || max_level == 0 {
+            return luma
+                .pixels()
+                .map(|p| (p[0] as usize * max_level + 127) / 255)
+                .collect();
+        }
+        let mut palette = Palette::default();
+        for level in 0..=max_level {
+            let value = (level * 255 / max_level) as u8;
+            palette.push(Rgb([value; 3]));
+        }
+        let mut img = RgbImage::from_fn(luma.width(), luma.height(), |x, y| {
+            Rgb([luma.get_pixel(x, y)[0]; 3])
+        });
+        dither(&mut img, &palette);
+        img.pixels().map(|p| palette.index_of(p)).collect()
+    }
+
+    pub fn encode<W: Write>(&mut self, w: &mut W, img: &RgbImage) -> Result<()> {
+        let img = self.adjustments.apply(Cow::Borrowed(img));
+        let luma = self.luma(&img);
+        let levels = self.levels(&luma);
+        let (width, height) = luma.dimensions();
+        let mut line = String::with_capacity(width as usize + 1);
+        for y in 0..height {
+            line.clear();
+            for x in 0..width {
+                let edge = self.is_edges.then(|| edge_glyph(&luma, x, y)).flatten();
+                line.push(edge.unwrap_or(self.ramp[levels[(y * width + x) as usize]]));
+            }
+            line.push('\n');
+            w.write_all(line.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// Line character following the edge at `(x, y)` found with the Sobel
+/// operator.
+fn edge_glyph(luma: &GrayImage, x: u32, y: u32) -> Option<char> {
+    let (width, height) = luma.dimensions();
+    let p = |dx: i32, dy: i32| {
+        let x = (x as i32 + dx).clamp(0, width as i32 - 1) as u32;
+        let y = (y as i32 + dy).clamp(0, height as i32 - 1) as u32;
+        luma.get_pixel(x, y)[0] as f32
+    };
+    let gx = p(1, -1) + 2.0 * p(1, 0) + p(1, 1) - p(-1, -1) - 2.0 * p(-1, 0) - p(-1, 1);
+    let gy = p(-1, 1) + 2.0 * p(0, 1) + p(1, 1) - p(-1, -1) - 2.0 * p(0, -1) - p(1, -1);
+    if gx.hypot(gy) < EDGE_THRESHOLD {
+        return None;
+    }
+    // The edge is perpendicular to the gradient, y points up on the screen.
+    let angle = (-gx).atan2(-gy).to_degrees().rem_euclid(180.0);
+    Some(match angle {
+        a if !(22.5..157.5).contains(&a) => '-',
+        a if a < 67.5 => '/',
+        a if a < 112.5 => '|',
+        _ => '\\',
+    })
+}
+
+impl Backend for AsciiEncoder {
+    fn emit(&mut self, mut w: &mut dyn Write, img: &RgbImage) -> Result<()> {
+        self.encode(&mut w, img)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ascii() {
+        let img = RgbImage::from_fn(4, 2, |x, _| Rgb([if x < 2 { 0 } else { 255 }; 3]));
+        let encode = |encoder: &mut AsciiEncoder| {
+            let mut out = Vec::new();
+            encoder.encode(&mut out, &img).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(encode(&mut AsciiEncoder::new()), "  @@\n");
+        assert_eq!(encode(&mut AsciiEncoder::new().edges(true)), " ||@\n");
+        assert_eq!(
+            encode(&mut AsciiEncoder::new().ramp("ab").columns(2)),
+            "ab\n"
+        );
+
+        let gradient = RgbImage::from_fn(64, 2, |x, _| Rgb([(x * 4) as u8; 3]));
+        let mut out = Vec::new();
+        AsciiEncoder::new()
+            .dither(true)
+            .encode(&mut out, &gradient)
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with(' ') && out.contains('@'));
+        assert!(out.trim_end().chars().all(|c| DEFAULT_RAMP.contains(c)));
+    }
+
+    #[test]
+    fn test_edge_glyph() {
+        let diagonal = GrayImage::from_fn(3, 3, |x, y| image::Luma([(x + y >= 2) as u8 * 255]));
+        assert_eq!(edge_glyph(&diagonal, 1, 1), Some('/'));
+        let horizontal = GrayImage::from_fn(3, 3, |_, y| image::Luma([(y >= 1) as u8 * 255]));
+        assert_eq!(edge_glyph(&horizontal, 1, 1), Some('-'));
+        assert_eq!(edge_glyph(&horizontal, 1, 2), None);
+    }
+}
@@ -0,0 +1,9 @@
+use anyhow::Result;
+use image::RgbImage;
+use std::io::Write;
+
+/// A terminal graphics protocol that can display an image.
+pub trait Backend {
+    /// Write everything needed to display `img` to `w`.
+    fn emit(&mut self, w: &mut dyn Write, img: &RgbImage) -> Result<()>;
+}
@@ -0,0 +1,89 @@
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use image::{codecs::png::PngEncoder, ImageEncoder, RgbImage};
+use std::{borrow::Cow, io::Write};
+
+use crate::{
+    passthrough::{Passthrough, PassthroughWriter},
+    Adjustments, Backend,
+};
+
+/// iTerm2 inline image protocol (`OSC 1337;File=`) backend, also supported
+/// by WezTerm, Konsole and mintty.
+#[derive(Debug, Default)]
+pub struct Iterm2Encoder {
+    passthrough: Passthrough,
+    adjustments: Adjustments,
+    payload: Vec<u8>,
+    data: String,
+}
+
+impl Iterm2Encoder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Wrap the output for tmux or GNU screen, see [`Passthrough::detect`].
+    pub fn passthrough(mut self, passthrough: Passthrough) -> Self {
+        self.passthrough = passthrough;
+        self
+    }
+
+    /// Correct the colors of the image before it is sent.
+    pub fn adjustments(mut self, adjustments: Adjustments) -> Self {
+        self.adjustments = adjustments;
+        self
+    }
+
+    pub fn encode<W: Write>(&mut self, w: &mut W, img: &RgbImage) -> Result<()> {
+        let img = self.adjustments.apply(Cow::Borrowed(img));
+        self.payload.clear();
+        PngEncoder::new(&mut self.payload).write_image(
+            img.as_raw(),
+            img.width(),
+            img.height(),
+            image::ExtendedColorType::Rgb8,
+        )?;
+        self.data.clear();
+        STANDARD.encode_string(&self.payload, &mut self.data);
+
+        let mut w = PassthroughWriter::new(w, self.passthrough)?;
+        write!(
+            w,
+            "\x1b]1337;File=inline=1;size={};width={}px;height={}px;preserveAspectRatio=1:",
+            self.payload.len(),
+            img.width(),
+            img.height(),
+        )?;
+        w.write_all(self.data.as_bytes())?;
+        w.write_all(b"\x07")?;
+        w.finish()?;
+        Ok(())
+    }
+}
+
+impl Backend for Iterm2Encoder {
+    fn emit(&mut self, mut w: &mut dyn Write, img: &RgbImage) -> Result<()> {
+        self.encode(&mut w, img)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    #[test]
+    fn test_iterm2() {
+        let img = RgbImage::from_pixel(3, 2, Rgb([0, 0, 255]));
+        let mut out = Vec::new();
+        Iterm2Encoder::new().encode(&mut out, &img).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let (header, data) = out.split_once(':').unwrap();
+        assert!(header.starts_with("\x1b]1337;File=inline=1;size="));
+        assert!(header.ends_with(";width=3px;height=2px;preserveAspectRatio=1"));
+        let png = STANDARD.decode(data.strip_suffix('\x07').unwrap()).unwrap();
+        let decoded = image::load_from_memory(&png).unwrap().to_rgb8();
+        assert_eq!(decoded, img);
+    }
+}
@@ -0,0 +1,140 @@
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use image::{codecs::png::PngEncoder, ImageEncoder, RgbImage};
+use std::{borrow::Cow, io::Write};
+
+use crate::{
+    passthrough::{Passthrough, PassthroughWriter},
+    Adjustments, Backend,
+};
+
+/// Maximum base64 payload size of a single escape code.
+const CHUNK_SIZE: usize = 4096;
+
+/// Pixel data format sent to the terminal.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum KittyFormat {
+    /// PNG compressed image (`f=100`)
+    #[default]
+    Png,
+    /// Raw 24-bit RGB pixels (`f=24`)
+    Rgb,
+}
+
+/// Kitty graphics protocol backend, also supported by Ghostty and WezTerm.
+#[derive(Debug, Default)]
+pub struct KittyEncoder {
+    format: KittyFormat,
+    passthrough: Passthrough,
+    adjustments: Adjustments,
+    payload: Vec<u8>,
+    data: String,
+}
+
+impl KittyEncoder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn format(mut self, format: KittyFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Wrap the output for tmux or GNU screen, see [`Passthrough::detect`].
+    pub fn passthrough(mut self, passthrough: Passthrough) -> Self {
+        self.passthrough = passthrough;
+        self
+    }
+
+    /// Correct the colors of the image before it is sent.
+    pub fn adjustments(mut self, adjustments: Adjustments) -> Self {
+        self.adjustments = adjustments;
+        self
+    }
+
+    pub fn encode<W: Write>(&mut self, w: &mut W, img: &RgbImage) -> Result<()> {
+        let img = self.adjustments.apply(Cow::Borrowed(img));
+        self.payload.clear();
+        let control = match self.format {
+            KittyFormat::Png => {
+                PngEncoder::new(&mut self.payload).write_image(
+                    img.as_raw(),
+                    img.width(),
+                    img.height(),
+                    image::ExtendedColorType::Rgb8,
+                )?;
+                "a=T,q=2,f=100".to_string()
+            }
+            KittyFormat::Rgb => {
+                self.payload.extend_from_slice(img.as_raw());
+                format!("a=T,q=2,f=24,s={},v={}", img.width(), img.height())
+            }
+        };
+        self.data.clear();
+        STANDARD.encode_string(&self.payload, &mut self.data);
+
+        let mut w = PassthroughWriter::new(w, self.passthrough)?;
+        let mut chunks = self
+            .data
+            .as_bytes()
+            .chunks(CHUNK_SIZE)
+            .enumerate()
+            .peekable();
+        while let Some((i, chunk)) = chunks.next() {
+            let more = chunks.peek().is_some() as u8;
+            if i == 0 {
+                write!(w, "\x1b_G{control},m={more};")?;
+            } else {
+                write!(w, "\x1b_Gm={more};")?;
+            }
+            w.write_all(chunk)?;
+            w.write_all(b"\x1b\\")?;
+        }
+        w.finish()?;
+        Ok(())
+    }
+}
+
+impl Backend for KittyEncoder {
+    fn emit(&mut self, mut w: &mut dyn Write, img: &RgbImage) -> Result<()> {
+        self.encode(&mut w, img)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    #[test]
+    fn test_kitty_rgb() {
+        let img = RgbImage::from_pixel(2, 1, Rgb([255, 0, 0]));
+        let mut out = Vec::new();
+        KittyEncoder::new()
+            .format(KittyFormat::Rgb)
+            .encode(&mut out, &img)
+            .unwrap();
+        assert_eq!(out, b"\x1b_Ga=T,q=2,f=24,s=2,v=1,m=0;/wAA/wAA\x1b\\");
+    }
+
+    #[test]
+    fn test_kitty_chunks() {
+        let img = RgbImage::from_pixel(64, 64, Rgb([255, 0, 0]));
+        let mut out = Vec::new();
+        KittyEncoder::new()
+            .format(KittyFormat::Rgb)
+            .encode(&mut out, &img)
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let chunks = out.split_terminator("\x1b\\").collect::<Vec<_>>();
+        assert_eq!(
+            chunks.len(),
+            (64 * 64 * 3usize).div_ceil(3) * 4 / CHUNK_SIZE
+        );
+        assert!(chunks[0].starts_with("\x1b_Ga=T,q=2,f=24,s=64,v=64,m=1;"));
+        assert!(chunks[1].starts_with("\x1b_Gm=1;"));
+        assert!(chunks.last().unwrap().starts_with("\x1b_Gm=0;"));
+    }
+}
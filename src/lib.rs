@@ -1,23 +1,55 @@
 use std::iter;
 
+mod adjust;
+mod ascii;
+mod backend;
+mod iterm2;
+mod kitty;
 pub mod median_cut;
+mod montage;
 mod octree;
+mod passthrough;
+mod protocol;
 mod queue;
+mod resize;
+mod session;
+pub mod sixel_decoder;
 pub mod sixel_encoder;
+mod terminal;
+mod text;
+mod transform;
 
 use image::{imageops::ColorMap, Rgb, RgbImage};
 use kuina::stack_vec::StackVec;
 
+pub use adjust::{Adjustments, AutoLevels};
+pub use ascii::AsciiEncoder;
+pub use backend::Backend;
+pub use iterm2::Iterm2Encoder;
+pub use kitty::{KittyEncoder, KittyFormat};
+pub use montage::montage;
 pub use octree::OctreeQuantizer;
-pub use sixel_encoder::EncoderBuilder;
+pub use passthrough::Passthrough;
+pub use protocol::Protocol;
+pub use resize::fit_image;
+pub use session::Session;
+pub use sixel_decoder::SixelDecoder;
+pub use sixel_encoder::{
+    open_animation, open_image, read_image, CursorOptions, EncodeStats, EncoderBuilder, Introducer,
+    PrinterOptions, SixelEncoder, Terminator,
+};
+pub use terminal::{query_background, query_device_attributes, WindowSize};
+pub use text::{TextColor, TextEncoder, TextMode};
+pub use transform::{Flip, Rotation, Transform};
 
 pub const MAX_COLORS: usize = 256;
+pub const MAX_HIGH_COLORS: usize = 4096;
 
 pub type Color = Rgb<u8>;
 
 #[derive(Default)]
 pub struct Palette {
-    colors: StackVec<Color, MAX_COLORS>,
+    colors: StackVec<Color, MAX_HIGH_COLORS>,
 }
 
 impl Palette {
@@ -34,6 +66,16 @@ impl Palette {
     }
 }
 
+impl FromIterator<Color> for Palette {
+    fn from_iter<I: IntoIterator<Item = Color>>(iter: I) -> Self {
+        let mut palette = Palette::default();
+        for color in iter {
+            palette.push(color);
+        }
+        palette
+    }
+}
+
 impl ColorMap for Palette {
     type Color = Color;
     fn index_of(&self, color: &Self::Color) -> usize {
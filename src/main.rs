@@ -1,36 +1,910 @@
-use anyhow::Result;
-use clap::Parser;
+use anyhow::{anyhow, bail, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use image::{imageops, ImageDecoder, ImageReader, Rgb, RgbImage};
 use log::debug;
-use rsixel::{EncoderBuilder, OctreeQuantizer, MAX_COLORS};
-use std::{io, path::PathBuf};
+use rsixel::{
+    fit_image, montage, open_animation, open_image, query_background, read_image, Adjustments,
+    AsciiEncoder, AutoLevels, Backend, Color, CursorOptions, EncoderBuilder, Flip, Introducer,
+    Iterm2Encoder, KittyEncoder, KittyFormat, OctreeQuantizer, Passthrough, PrinterOptions,
+    Protocol, Quantizer, Rotation, SixelDecoder, Terminator, TextColor, TextEncoder, TextMode,
+    Transform, WindowSize, MAX_COLORS, MAX_HIGH_COLORS,
+};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    env,
+    fmt::{self, Write as _},
+    fs::{self, File},
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    process, thread,
+    time::Duration,
+};
+
+/// Delay of animation frames without one, browsers use the same.
+const DEFAULT_FRAME_DELAY: Duration = Duration::from_millis(100);
+
+/// Space between grid thumbnails in pixels.
+const GRID_GAP: u32 = 8;
+
+/// Cell width in pixels assumed when the terminal doesn't report it.
+const FALLBACK_CELL_WIDTH: u32 = 10;
+
+/// Cell height in pixels assumed when the terminal doesn't report it.
+const FALLBACK_CELL_HEIGHT: u32 = 20;
+
+/// Color that transparent pixels are composited over by default.
+const DEFAULT_BACKGROUND: Color = Rgb([0, 0, 0]);
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum PassthroughArg {
+    /// Detect from `TMUX` and `TERM` environment variables
+    Auto,
+    Tmux,
+    Screen,
+    None,
+}
+
+/// Color that transparent pixels are composited over.
+#[derive(Clone, Copy, Debug)]
+enum Background {
+    Color(Color),
+    /// Default background color reported by the terminal
+    Terminal,
+}
 
 /// Convert image to sixel format
 #[derive(Parser, Debug)]
 #[command(version, about, long_about= None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
-    /// Input image path
-    img: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    /// Color palette size
+    #[command(flatten)]
+    encode: EncodeArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct EncodeArgs {
+    /// Input image paths or glob patterns, `-` reads an image from stdin
+    #[arg(required = true)]
+    img: Vec<PathBuf>,
+
+    /// Print the file name above every image
+    #[arg(long, default_value_t = false)]
+    caption: bool,
+
+    /// Write the output to a file instead of stdout
+    #[arg(short, long, conflicts_with = "output_dir")]
+    output: Option<PathBuf>,
+
+    /// Write every image to its own NAME.six file in this directory
+    #[arg(long)]
+    output_dir: Option<PathBuf>,
+
+    /// Show the images as thumbnails in one grid sized to the terminal width
+    #[arg(long, default_value_t = false, conflicts_with = "output_dir")]
+    grid: bool,
+
+    /// Size of the grid thumbnails in pixels
+    #[arg(long, default_value_t = 128, requires = "grid")]
+    tile_size: u32,
+
+    /// Number of thumbnails in a grid row [default: fit the terminal width]
+    #[arg(long, requires = "grid")]
+    grid_columns: Option<u32>,
+
+    /// Encode every grid row as its own image sharing one palette, captions
+    /// are printed below the rows and imply this
+    #[arg(long, default_value_t = false, requires = "grid")]
+    grid_rows: bool,
+
+    #[command(flatten)]
+    backend: BackendArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct BackendArgs {
+    /// Terminal graphics protocol [default: detected from the environment and terminal]
+    #[arg(long, value_enum)]
+    protocol: Option<Protocol>,
+
+    /// Scale the image down to at most this many pixels wide
+    #[arg(long)]
+    width: Option<u32>,
+
+    /// Scale the image down to at most this many pixels high
+    #[arg(long)]
+    height: Option<u32>,
+
+    /// Keep only the X,Y,WIDTH,HEIGHT pixel rectangle of the image
+    #[arg(long, value_parser = parse_crop)]
+    crop: Option<(u32, u32, u32, u32)>,
+
+    /// Rotate the image clockwise by this many degrees, after cropping
+    #[arg(long, value_enum)]
+    rotate: Option<Rotation>,
+
+    /// Mirror the image horizontally or vertically, after rotating
+    #[arg(long, value_enum)]
+    flip: Option<Flip>,
+
+    /// Color that transparent pixels are composited over, as #RRGGBB or
+    /// `terminal` to query the terminal background [default: black]
+    #[arg(long, value_parser = parse_background)]
+    background: Option<Background>,
+
+    /// Brightness offset from -1.0 (black) to 1.0 (white)
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    brightness: f32,
+
+    /// Contrast factor, 1.0 keeps the image unchanged
+    #[arg(long, default_value_t = 1.0)]
+    contrast: f32,
+
+    /// Gamma correction, values above 1.0 brighten mid tones
+    #[arg(long, default_value_t = 1.0)]
+    gamma: f32,
+
+    /// Saturation factor, 0.0 produces a grayscale image
+    #[arg(long, default_value_t = 1.0)]
+    saturation: f32,
+
+    /// Correct the tones automatically from the image histogram, before the
+    /// other adjustments
+    #[arg(long, value_enum)]
+    auto_levels: Option<AutoLevels>,
+
+    /// Kitty pixel data format
+    #[arg(long, value_enum, default_value_t)]
+    kitty_format: KittyFormat,
+
+    /// Characters used by the text protocol
+    #[arg(long, value_enum, default_value_t)]
+    text_mode: TextMode,
+
+    /// Colors used by the text protocol
+    #[arg(long, value_enum, default_value_t)]
+    text_color: TextColor,
+
+    /// Maximum width of text and ASCII output in cells [default: terminal width]
+    #[arg(long)]
+    columns: Option<u32>,
+
+    /// Characters used by the ASCII protocol, ordered from dark to bright
+    #[arg(long, default_value = " .:-=+*#%@")]
+    ramp: String,
+
+    /// Draw edges with line characters in ASCII output
+    #[arg(long, default_value_t = false)]
+    edges: bool,
+
+    /// Color palette size [default: number of registers]
+    #[arg(short, long)]
+    palette_size: Option<usize>,
+
+    /// Number of color registers supported by the terminal, values above 256
+    /// enable high-color mode
     #[arg(short, long, default_value_t=MAX_COLORS)]
-    palette_size: usize,
+    registers: usize,
+
+    /// Quantize every group of N six pixel high bands with its own palette,
+    /// 0 uses a single palette for the whole image
+    #[arg(short, long, default_value_t = 0)]
+    adaptive_palette: usize,
 
     /// Use dithering
     #[arg(short, long, default_value_t = false)]
     dither: bool,
 
+    /// Control sequence introducer
+    #[arg(long, value_enum, default_value_t)]
+    introducer: Introducer,
+
+    /// Sequence terminating the sixel data
+    #[arg(long, value_enum, default_value_t)]
+    terminator: Terminator,
+
+    /// Wrap output for terminal multiplexers
+    #[arg(long, value_enum, default_value_t = PassthroughArg::Auto)]
+    passthrough: PassthroughArg,
+
+    /// Pixel aspect ratio as vertical:horizontal units, e.g. 2:1 for VT340
+    #[arg(long, value_parser = parse_aspect, default_value = "1:1")]
+    aspect: (u32, u32),
+
+    /// Select the pixel aspect ratio with the P1 DCS parameter
+    #[arg(long, default_value_t = false)]
+    aspect_macro: bool,
+
+    /// Omit raster attributes
+    #[arg(long, default_value_t = false)]
+    no_raster: bool,
+
+    /// Wrap output lines at N bytes, 0 disables wrapping
+    #[arg(long, default_value_t = 0)]
+    line_width: usize,
+
+    /// Save the cursor before the image and restore it afterwards
+    #[arg(long, default_value_t = false)]
+    save_cursor: bool,
+
+    /// Draw the image at the given one-based ROW,COL cell
+    #[arg(long, value_parser = parse_position)]
+    position: Option<(u32, u32)>,
+
+    /// Start a new line after the image
+    #[arg(long, default_value_t = false)]
+    move_below: bool,
+
+    /// Enable sixel display mode (DECSDM) while drawing the image
+    #[arg(long, default_value_t = false)]
+    display_mode: bool,
+
+    /// Produce output for a sixel printer (LA50, LA75)
+    #[arg(long, default_value_t = false)]
+    printer: bool,
+
+    /// Printer horizontal grid size in decipoints, 0 selects the printer default
+    #[arg(long, default_value_t = 0, requires = "printer")]
+    printer_grid_size: u32,
+
+    /// Print every pixel as 2x2 dots
+    #[arg(long, default_value_t = false, requires = "printer")]
+    pixel_doubling: bool,
+
+    /// Forward output to the printer attached to the terminal
+    #[arg(long, default_value_t = false, requires = "printer")]
+    printer_controller: bool,
+
     /// Debug
     #[arg(long, default_value_t = false)]
     debug: bool,
 }
 
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Display an image, the default when no command is given
+    Encode(EncodeArgs),
+    /// Convert sixel images to PNG
+    Decode(DecodeArgs),
+    /// Print image and palette statistics
+    Info(InfoArgs),
+    /// Export the quantized color palette of an image
+    Palette(PaletteArgs),
+    /// Play an animated GIF, PNG or WebP image
+    Play(PlayArgs),
+    /// Print the detected terminal capabilities
+    Detect,
+}
+
+#[derive(clap::Args, Debug)]
+struct DecodeArgs {
+    /// Sixel file path, `-` reads from stdin
+    #[arg(default_value = "-")]
+    input: PathBuf,
+
+    /// Output image path, several images are written as NAME-1.EXT, NAME-2.EXT, ...
+    /// [default: input path with png extension]
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+struct InfoArgs {
+    /// Input image path
+    img: PathBuf,
+
+    /// Color palette size used for the sixel statistics
+    #[arg(short, long, default_value_t = MAX_COLORS)]
+    palette_size: usize,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+enum PaletteFormat {
+    /// `#rrggbb` per line
+    #[default]
+    Hex,
+    /// GIMP palette
+    Gpl,
+    /// Sixel color definitions
+    Sixel,
+}
+
+#[derive(clap::Args, Debug)]
+struct PaletteArgs {
+    /// Input image path
+    img: PathBuf,
+
+    /// Color palette size
+    #[arg(short, long, default_value_t = MAX_COLORS)]
+    palette_size: usize,
+
+    /// Palette file format
+    #[arg(short, long, value_enum, default_value_t)]
+    format: PaletteFormat,
+
+    /// Output file path [default: stdout]
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+struct PlayArgs {
+    /// Animated image path
+    img: PathBuf,
+
+    /// Number of times to play the animation, 0 loops forever
+    #[arg(long, default_value_t = 1)]
+    loops: u32,
+
+    #[command(flatten)]
+    backend: BackendArgs,
+}
+
+fn parse_aspect(s: &str) -> Result<(u32, u32)> {
+    let (pan, pad) = s
+        .split_once(':')
+        .ok_or_else(|| anyhow!("expected PAN:PAD"))?;
+    Ok((pan.parse()?, pad.parse()?))
+}
+
+fn parse_position(s: &str) -> Result<(u32, u32)> {
+    let (row, col) = s
+        .split_once(',')
+        .ok_or_else(|| anyhow!("expected ROW,COL"))?;
+    Ok((row.parse()?, col.parse()?))
+}
+
+fn parse_background(s: &str) -> Result<Background> {
+    if s == "terminal" {
+        return Ok(Background::Terminal);
+    }
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if hex.len() != 6 {
+        bail!("expected #RRGGBB or terminal");
+    }
+    let color = u32::from_str_radix(hex, 16)?;
+    let [_, r, g, b] = color.to_be_bytes();
+    Ok(Background::Color(Rgb([r, g, b])))
+}
+
+fn parse_crop(s: &str) -> Result<(u32, u32, u32, u32)> {
+    match s
+        .split(',')
+        .map(str::parse)
+        .collect::<Result<Vec<_>, _>>()?[..]
+    {
+        [x, y, width, height] => Ok((x, y, width, height)),
+        _ => bail!("expected X,Y,WIDTH,HEIGHT"),
+    }
+}
+
+fn decode(args: DecodeArgs) -> Result<()> {
+    let is_stdin = args.input == Path::new("-");
+    let data = if is_stdin {
+        let mut data = Vec::new();
+        io::stdin().lock().read_to_end(&mut data)?;
+        data
+    } else {
+        fs::read(&args.input)?
+    };
+    let images = SixelDecoder::new().decode(&data)?;
+    debug!("Decoded {} images", images.len());
+    let output = match args.output {
+        Some(output) => output,
+        None if !is_stdin => args.input.with_extension("png"),
+        None => bail!("--output is required when reading from stdin"),
+    };
+    match &images[..] {
+        [] => bail!("no sixel images found"),
+        [img] => img.save(&output)?,
+        images => {
+            let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+            let ext = output
+                .extension()
+                .unwrap_or("png".as_ref())
+                .to_string_lossy();
+            for (i, img) in images.iter().enumerate() {
+                img.save(output.with_file_name(format!("{stem}-{}.{ext}", i + 1)))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+impl BackendArgs {
+    fn transform(&self) -> Transform {
+        Transform {
+            crop: self.crop,
+            rotate: self.rotate,
+            flip: self.flip,
+        }
+    }
+
+    /// Resolve the background color, the terminal is only queried here.
+    fn background(&self) -> Color {
+        match self.background {
+            Some(Background::Color(color)) => color,
+            Some(Background::Terminal) => query_background().unwrap_or_else(|| {
+                debug!("Terminal did not report its background color");
+                DEFAULT_BACKGROUND
+            }),
+            None => DEFAULT_BACKGROUND,
+        }
+    }
+
+    fn adjustments(&self) -> Adjustments {
+        Adjustments {
+            brightness: self.brightness,
+            contrast: self.contrast,
+            gamma: self.gamma,
+            saturation: self.saturation,
+            auto_levels: self.auto_levels,
+        }
+    }
+}
+
+/// Files default to sixel without passthrough as the terminal that displays
+/// them is not known.
+fn build_backend(
+    args: &BackendArgs,
+    is_file_output: bool,
+    palette: &[Color],
+    adjustments: Adjustments,
+) -> Box<dyn Backend> {
+    let passthrough = match args.passthrough {
+        PassthroughArg::Auto if is_file_output => Passthrough::None,
+        PassthroughArg::Auto => Passthrough::detect(),
+        PassthroughArg::Tmux => Passthrough::Tmux,
+        PassthroughArg::Screen => Passthrough::Screen,
+        PassthroughArg::None => Passthrough::None,
+    };
+    let mut builder = EncoderBuilder::<OctreeQuantizer>::new()
+        .palette_size(args.palette_size.unwrap_or(args.registers))
+        .registers(args.registers)
+        .adaptive_palette(args.adaptive_palette)
+        .dither(args.dither)
+        .introducer(args.introducer)
+        .terminator(args.terminator)
+        .passthrough(passthrough)
+        .pixel_aspect(args.aspect.0, args.aspect.1)
+        .aspect_macro(args.aspect_macro)
+        .raster_attributes(!args.no_raster)
+        .line_width(args.line_width)
+        .palette(palette)
+        .adjustments(adjustments)
+        .cursor(CursorOptions {
+            save_restore: args.save_cursor,
+            position: args.position,
+            move_below: args.move_below,
+            display_mode: args.display_mode,
+            cell_height: WindowSize::detect()
+                .and_then(|size| size.cell_height())
+                .map_or(FALLBACK_CELL_HEIGHT, u32::from),
+        })
+        .debug(args.debug);
+    if args.printer {
+        builder = builder.printer(PrinterOptions {
+            grid_size: args.printer_grid_size,
+            pixel_doubling: args.pixel_doubling,
+            controller_mode: args.printer_controller,
+        });
+    }
+    let protocol = args.protocol.unwrap_or_else(|| {
+        if is_file_output {
+            Protocol::Sixel
+        } else {
+            Protocol::detect()
+        }
+    });
+    let columns = args
+        .columns
+        .unwrap_or_else(|| WindowSize::detect().map_or(80, |size| size.columns as u32));
+    debug!("Using protocol: {protocol:?}");
+    let backend: Box<dyn Backend> = match protocol {
+        Protocol::Sixel => Box::new(builder.build()),
+        Protocol::Kitty => Box::new(
+            KittyEncoder::new()
+                .format(args.kitty_format)
+                .passthrough(passthrough)
+                .adjustments(adjustments),
+        ),
+        Protocol::Iterm2 => Box::new(
+            Iterm2Encoder::new()
+                .passthrough(passthrough)
+                .adjustments(adjustments),
+        ),
+        Protocol::Text => Box::new(
+            TextEncoder::<OctreeQuantizer>::new()
+                .mode(args.text_mode)
+                .color(args.text_color)
+                .palette_size(args.palette_size.unwrap_or(0))
+                .dither(args.dither)
+                .columns(columns)
+                .adjustments(adjustments),
+        ),
+        Protocol::Ascii => Box::new(
+            AsciiEncoder::new()
+                .ramp(&args.ramp)
+                .dither(args.dither)
+                .edges(args.edges)
+                .columns(columns)
+                .adjustments(adjustments),
+        ),
+    };
+    backend
+}
+
+/// Image file or stdin given on the command line.
+enum Input {
+    Stdin,
+    Path(PathBuf),
+}
+
+impl Input {
+    fn open(&self, background: Color) -> Result<RgbImage> {
+        match self {
+            Input::Stdin => read_image(io::stdin().lock(), background),
+            Input::Path(path) => open_image(path, background),
+        }
+    }
+
+    /// File name without the directory, used for grid captions.
+    fn file_name(&self) -> String {
+        match self {
+            Input::Stdin => "-".to_string(),
+            Input::Path(path) => path
+                .file_name()
+                .unwrap_or(path.as_os_str())
+                .to_string_lossy()
+                .into_owned(),
+        }
+    }
+
+    /// Name of the file the image is written to with `--output-dir`.
+    fn output_name(&self) -> PathBuf {
+        match self {
+            Input::Stdin => PathBuf::from("stdin.six"),
+            Input::Path(path) => {
+                let mut name = path.file_stem().unwrap_or_default().to_owned();
+                name.push(".six");
+                PathBuf::from(name)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Input::Stdin => write!(f, "-"),
+            Input::Path(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// Expand glob patterns that the shell left alone, e.g. when quoted.
+fn expand_inputs(args: &[PathBuf]) -> Result<Vec<Input>> {
+    let mut inputs = Vec::new();
+    for arg in args {
+        if arg == Path::new("-") {
+            inputs.push(Input::Stdin);
+            continue;
+        }
+        let pattern = arg.to_string_lossy();
+        if arg.exists() || !pattern.contains(['*', '?', '[']) {
+            inputs.push(Input::Path(arg.clone()));
+            continue;
+        }
+        let paths = glob::glob(&pattern)?.collect::<Result<Vec<_>, _>>()?;
+        if paths.is_empty() {
+            bail!("no files match {pattern}");
+        }
+        inputs.extend(paths.into_iter().map(Input::Path));
+    }
+    Ok(inputs)
+}
+
+fn encode_input(
+    args: &EncodeArgs,
+    backend: &mut dyn Backend,
+    w: &mut dyn Write,
+    input: &Input,
+    background: Color,
+) -> Result<()> {
+    let img = args.backend.transform().apply(input.open(background)?)?;
+    let img = fit_image(&img, args.backend.width, args.backend.height);
+    if args.caption {
+        writeln!(w, "{input}")?;
+    }
+    backend.emit(w, &img)
+}
+
+/// Write to a temporary file that replaces `path` once complete, so readers
+/// never see a partially written file.
+fn write_atomic(path: &Path, f: impl FnOnce(&mut dyn Write) -> Result<()>) -> Result<()> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("{} is not a file path", path.display()))?;
+    let tmp_path =
+        path.with_file_name(format!(".{}.{}.tmp", name.to_string_lossy(), process::id()));
+    let result = (|| {
+        let mut w = BufWriter::new(File::create(&tmp_path)?);
+        f(&mut w)?;
+        w.into_inner().map_err(|err| err.into_error())?.sync_all()?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Run `f` for every input, failed inputs are reported and skipped unless
+/// there is only one.
+fn for_each_input(inputs: &[Input], mut f: impl FnMut(&Input) -> Result<()>) -> Result<()> {
+    if let [input] = inputs {
+        return f(input);
+    }
+    let mut failed = 0;
+    for input in inputs {
+        if let Err(err) = f(input) {
+            let is_broken_pipe = err
+                .downcast_ref::<io::Error>()
+                .is_some_and(|err| err.kind() == io::ErrorKind::BrokenPipe);
+            if is_broken_pipe {
+                return Err(err);
+            }
+            eprintln!("{input}: {err:#}");
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        bail!("{failed} of {} images failed", inputs.len());
+    }
+    Ok(())
+}
+
+fn encode_grid(
+    args: &EncodeArgs,
+    inputs: &[Input],
+    w: &mut dyn Write,
+    is_file_output: bool,
+) -> Result<()> {
+    let tile_size = args.tile_size.max(1);
+    let transform = args.backend.transform();
+    let background = args.backend.background();
+    let mut images = Vec::with_capacity(inputs.len());
+    let mut names = Vec::with_capacity(inputs.len());
+    let result = for_each_input(inputs, |input| {
+        let img = transform.apply(input.open(background)?)?;
+        images.push(fit_image(&img, Some(tile_size), Some(tile_size)).into_owned());
+        names.push(input.file_name());
+        Ok(())
+    });
+    if images.is_empty() {
+        return result;
+    }
+
+    let window = WindowSize::detect();
+    let cell_width = window
+        .and_then(|size| size.cell_width())
+        .map_or(FALLBACK_CELL_WIDTH, u32::from);
+    let columns = args.grid_columns.unwrap_or_else(|| {
+        let width = window.map_or(80, |size| size.columns as u32) * cell_width;
+        (width + GRID_GAP) / (tile_size + GRID_GAP)
+    });
+    let columns = columns.clamp(1, images.len() as u32);
+    let adjustments = args.backend.adjustments();
+    let sheet = montage(&images, columns, tile_size, GRID_GAP, background);
+    if !args.grid_rows && !args.caption {
+        build_backend(&args.backend, is_file_output, &[], adjustments).emit(w, &sheet)?;
+        return result;
+    }
+
+    // Adjust and quantize the whole sheet once so that all rows share the
+    // palette and auto levels.
+    let sheet = adjustments.apply(Cow::Owned(sheet));
+    let palette_size = args.backend.palette_size.unwrap_or(args.backend.registers);
+    let palette = OctreeQuantizer::quantize(&sheet, palette_size);
+    let mut backend = build_backend(
+        &args.backend,
+        is_file_output,
+        palette.get_palette(),
+        Adjustments::default(),
+    );
+    let caption_width = ((tile_size + GRID_GAP) / cell_width).max(1) as usize;
+    for (row, names) in names.chunks(columns as usize).enumerate() {
+        let y = row as u32 * (tile_size + GRID_GAP);
+        let img = imageops::crop_imm(&*sheet, 0, y, sheet.width(), tile_size).to_image();
+        backend.emit(w, &img)?;
+        if args.caption {
+            let line = names
+                .iter()
+                .map(|name| {
+                    let name = name.chars().take(caption_width - 1).collect::<String>();
+                    format!("{name:caption_width$}")
+                })
+                .collect::<String>();
+            writeln!(w, "{}", line.trim_end())?;
+        }
+    }
+    result
+}
+
+fn encode(args: EncodeArgs) -> Result<()> {
+    let inputs = expand_inputs(&args.img)?;
+    let is_file_output = args.output.is_some() || args.output_dir.is_some();
+    if args.grid {
+        return match &args.output {
+            Some(output) => write_atomic(output, |w| encode_grid(&args, &inputs, w, true)),
+            None => encode_grid(&args, &inputs, &mut io::stdout().lock(), false),
+        };
+    }
+    let background = args.backend.background();
+    let mut backend = build_backend(
+        &args.backend,
+        is_file_output,
+        &[],
+        args.backend.adjustments(),
+    );
+    if let Some(output) = &args.output {
+        write_atomic(output, |w| {
+            for_each_input(&inputs, |input| {
+                encode_input(&args, &mut *backend, w, input, background)
+            })
+        })
+    } else if let Some(output_dir) = &args.output_dir {
+        let mut names = HashMap::new();
+        for input in &inputs {
+            if let Some(other) = names.insert(input.output_name(), input) {
+                bail!(
+                    "{other} and {input} would both be written to {}",
+                    output_dir.join(input.output_name()).display()
+                );
+            }
+        }
+        fs::create_dir_all(output_dir)?;
+        for_each_input(&inputs, |input| {
+            write_atomic(&output_dir.join(input.output_name()), |w| {
+                encode_input(&args, &mut *backend, w, input, background)
+            })
+        })
+    } else {
+        let mut stdout = io::stdout().lock();
+        for_each_input(&inputs, |input| {
+            encode_input(&args, &mut *backend, &mut stdout, input, background)
+        })
+    }
+}
+
+fn info(args: InfoArgs) -> Result<()> {
+    // The reader only detects the format and color type, the pixels are
+    // decoded upright and flattened like for encoding.
+    let reader = ImageReader::open(&args.img)?.with_guessed_format()?;
+    let format = reader.format();
+    let color_type = reader.into_decoder()?.color_type();
+    let img = open_image(&args.img, DEFAULT_BACKGROUND)?;
+    let unique_colors = img.pixels().collect::<HashSet<_>>().len();
+    let (_, stats) = EncoderBuilder::<OctreeQuantizer>::new()
+        .palette_size(args.palette_size)
+        .registers(args.palette_size)
+        .build()
+        .encode_to_vec(&img)?;
+    println!("File: {}", args.img.display());
+    match format {
+        Some(format) => println!("Format: {format:?}"),
+        None => println!("Format: unknown"),
+    }
+    println!("Dimensions: {}x{}", img.width(), img.height());
+    println!("Color type: {color_type:?}");
+    println!("Unique colors: {unique_colors}");
+    println!("Palette size: {}", stats.palette_size);
+    println!("Sixel bands: {}", stats.band_count);
+    println!("Sixel color switches: {}", stats.color_switches);
+    println!("Sixel size: {} bytes", stats.output_bytes);
+    Ok(())
+}
+
+fn palette(args: PaletteArgs) -> Result<()> {
+    let img = open_image(&args.img, DEFAULT_BACKGROUND)?;
+    let palette = OctreeQuantizer::quantize(&img, args.palette_size.clamp(1, MAX_HIGH_COLORS));
+    let mut out = String::new();
+    if let PaletteFormat::Gpl = args.format {
+        let name = args.img.file_stem().unwrap_or_default().to_string_lossy();
+        writeln!(out, "GIMP Palette\nName: {name}\nColumns: 16\n#")?;
+    }
+    for (i, color) in palette.get_palette().iter().enumerate() {
+        let [r, g, b] = color.0;
+        match args.format {
+            PaletteFormat::Hex => writeln!(out, "#{r:02x}{g:02x}{b:02x}"),
+            PaletteFormat::Gpl => writeln!(out, "{r:3} {g:3} {b:3}\t#{r:02x}{g:02x}{b:02x}"),
+            PaletteFormat::Sixel => {
+                let [r, g, b] = color.0.map(|c| c as u16 * 100 / 255);
+                writeln!(out, "#{i};2;{r};{g};{b}")
+            }
+        }?;
+    }
+    match args.output {
+        Some(output) => write_atomic(&output, |w| Ok(w.write_all(out.as_bytes())?))?,
+        None => io::stdout().lock().write_all(out.as_bytes())?,
+    }
+    Ok(())
+}
+
+fn play(args: PlayArgs) -> Result<()> {
+    let mut backend = build_backend(&args.backend, false, &[], args.backend.adjustments());
+    let transform = args.backend.transform();
+    let frames = open_animation(&args.img, args.backend.background())?
+        .into_iter()
+        .map(|(img, delay)| {
+            let img = transform.apply(img)?;
+            let img = fit_image(&img, args.backend.width, args.backend.height);
+            let mut data = Vec::new();
+            backend.emit(&mut data, &img)?;
+            let delay = if delay.is_zero() {
+                DEFAULT_FRAME_DELAY
+            } else {
+                delay
+            };
+            Ok((data, delay))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if frames.is_empty() {
+        bail!("{} has no frames", args.img.display());
+    }
+    debug!("Playing {} frames", frames.len());
+
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(b"\x1b7")?;
+    let mut played = 0;
+    while args.loops == 0 || played < args.loops {
+        for (data, delay) in &frames {
+            stdout.write_all(b"\x1b8")?;
+            stdout.write_all(data)?;
+            stdout.flush()?;
+            thread::sleep(*delay);
+        }
+        played += 1;
+    }
+    Ok(())
+}
+
+fn detect() -> Result<()> {
+    let protocol = Protocol::detect();
+    let protocol = protocol.to_possible_value().expect("no skipped variants");
+    println!("Protocol: {}", protocol.get_name());
+    println!("Passthrough: {:?}", Passthrough::detect());
+    println!("TERM: {}", env::var("TERM").unwrap_or_default());
+    println!(
+        "TERM_PROGRAM: {}",
+        env::var("TERM_PROGRAM").unwrap_or_default()
+    );
+    match WindowSize::detect() {
+        Some(size) if size.width > 0 => println!(
+            "Size: {}x{} cells, {}x{} pixels",
+            size.columns, size.rows, size.width, size.height
+        ),
+        Some(size) => println!("Size: {}x{} cells", size.columns, size.rows),
+        None => println!("Size: unknown, not a terminal"),
+    }
+    Ok(())
+}
+
 fn main() -> Result<()> {
     env_logger::init();
     let args = Args::try_parse()?;
     debug!("Recieved args: {args:#?}");
-    let sixel_encoder = EncoderBuilder::<OctreeQuantizer>::new(&args.img)
-        .debug(args.debug)
-        .build()?;
-    sixel_encoder.image_to_sixel(&mut io::stdout().lock(), args.palette_size, args.dither)?;
-    Ok(())
+    match args.command {
+        None => encode(args.encode),
+        Some(Command::Encode(args)) => encode(args),
+        Some(Command::Decode(args)) => decode(args),
+        Some(Command::Info(args)) => info(args),
+        Some(Command::Palette(args)) => palette(args),
+        Some(Command::Play(args)) => play(args),
+        Some(Command::Detect) => detect(),
+    }
 }
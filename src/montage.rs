@@ -0,0 +1,50 @@
+use image::{imageops, RgbImage};
+
+use crate::{fit_image, Color};
+
+/// Lay out thumbnails of `images` in a grid with `columns` tiles per row.
+/// Every image is scaled down to fit a `tile_size` square and centered in
+/// it, tiles are separated by `gap` pixels of `background`.
+pub fn montage(
+    images: &[RgbImage],
+    columns: u32,
+    tile_size: u32,
+    gap: u32,
+    background: Color,
+) -> RgbImage {
+    let columns = columns.clamp(1, images.len().max(1) as u32);
+    let rows = (images.len() as u32).div_ceil(columns).max(1);
+    let step = tile_size + gap;
+    let mut sheet = RgbImage::from_pixel(columns * step - gap, rows * step - gap, background);
+    for (i, img) in images.iter().enumerate() {
+        let thumbnail = fit_image(img, Some(tile_size), Some(tile_size));
+        let (column, row) = (i as u32 % columns, i as u32 / columns);
+        let x = column * step + (tile_size - thumbnail.width()) / 2;
+        let y = row * step + (tile_size - thumbnail.height()) / 2;
+        imageops::replace(&mut sheet, &*thumbnail, x as i64, y as i64);
+    }
+    sheet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    #[test]
+    fn test_montage() {
+        let images = [
+            RgbImage::from_pixel(20, 10, Rgb([255, 0, 0])),
+            RgbImage::from_pixel(4, 4, Rgb([0, 255, 0])),
+            RgbImage::from_pixel(10, 10, Rgb([0, 0, 255])),
+        ];
+        let sheet = montage(&images, 2, 10, 2, Rgb([0, 0, 0]));
+        assert_eq!(sheet.dimensions(), (22, 22));
+        assert_eq!(*sheet.get_pixel(0, 1), Rgb([0, 0, 0]));
+        assert_eq!(*sheet.get_pixel(0, 2), Rgb([255, 0, 0]));
+        assert_eq!(*sheet.get_pixel(15, 5), Rgb([0, 255, 0]));
+        assert_eq!(*sheet.get_pixel(10, 5), Rgb([0, 0, 0]));
+        assert_eq!(*sheet.get_pixel(5, 16), Rgb([0, 0, 255]));
+        assert_eq!(*sheet.get_pixel(16, 16), Rgb([0, 0, 0]));
+    }
+}
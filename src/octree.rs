@@ -1,18 +1,24 @@
 use image::{Rgb, RgbImage};
 use log::debug;
-use std::{array, iter};
+use std::{array, collections::VecDeque, iter};
 
 use crate::queue::Queue;
-use crate::{Palette, Quantizer, MAX_COLORS};
+use crate::{Palette, Quantizer, MAX_HIGH_COLORS};
 
 const MAX_LEVEL: u8 = 6;
-const MAX_NODES: usize = 768;
+
+/// Most nodes an octree reduced to `color_count` leaves holds. An insert adds
+/// at most one leaf before the tree is reduced again, and every node lies on
+/// the path from the root to a leaf.
+fn max_nodes(color_count: usize) -> usize {
+    1 + (color_count + 1) * MAX_LEVEL as usize
+}
 
 #[derive(Debug, Default)]
 struct Node {
     rgb: [u32; 3],
     count: u32,
-    index: u8,
+    index: u16,
     level: u8,
     children_count: u8,
     is_leaf: bool,
@@ -34,17 +40,19 @@ impl Node {
 
 #[derive(Debug)]
 struct Pool {
-    nodes: [Node; MAX_NODES],
-    ids: Queue<u32, MAX_NODES>,
+    nodes: Vec<Node>,
+    ids: Queue<u32>,
 }
 
 impl Pool {
-    fn new() -> Self {
-        let mut ids = Queue::new();
-        let nodes = array::from_fn(|i| {
-            ids.push(i as u32);
-            Node::default()
-        });
+    fn new(size: usize) -> Self {
+        let mut ids = Queue::new(size);
+        let nodes = (0..size)
+            .map(|i| {
+                ids.push(i as u32);
+                Node::default()
+            })
+            .collect();
         Self { nodes, ids }
     }
 
@@ -79,23 +87,23 @@ fn get_color_index(color: Rgb<u8>, level: u8) -> usize {
 
 #[derive(Debug)]
 struct Reducible {
-    levels: [Queue<u32, MAX_COLORS>; MAX_LEVEL as usize],
+    levels: [VecDeque<u32>; MAX_LEVEL as usize],
 }
 
 impl Reducible {
     fn new() -> Self {
         Self {
-            levels: array::from_fn(|_| Queue::new()),
+            levels: Default::default(),
         }
     }
 
     fn push(&mut self, node_id: u32, level: u8) {
-        self.levels[level as usize].push(node_id);
+        self.levels[level as usize].push_back(node_id);
     }
 
     fn pop(&mut self) -> Option<u32> {
         for level in self.levels.iter_mut().rev() {
-            if let Some(node_id) = level.pop() {
+            if let Some(node_id) = level.pop_front() {
                 return Some(node_id);
             }
         }
@@ -113,7 +121,7 @@ struct Octree {
 
 impl Octree {
     fn new(color_count: usize) -> Self {
-        let mut pool = Pool::new();
+        let mut pool = Pool::new(max_nodes(color_count));
         let root = pool.create();
         pool.get_mut(root).is_leaf = true;
         Self {
@@ -130,7 +138,7 @@ impl Octree {
         F: FnMut(u32, &Node),
     {
         let mut f = f;
-        let mut queue = Queue::<u32, MAX_NODES>::new();
+        let mut queue = Queue::new(self.pool.nodes.len());
         queue.push(self.root);
         while let Some(node_id) = queue.pop() {
             let node = self.pool.get(node_id);
@@ -146,7 +154,7 @@ impl Octree {
         F: FnMut(u32, &mut Node),
     {
         let mut f = f;
-        let mut queue = Queue::<u32, MAX_NODES>::new();
+        let mut queue = Queue::new(self.pool.nodes.len());
         queue.push(self.root);
         while let Some(node_id) = queue.pop() {
             let node = self.pool.get_mut(node_id);
@@ -252,7 +260,7 @@ impl Octree {
         let mut palette = Palette::default();
         self.traverse_mut(|_, node| {
             if node.is_leaf {
-                node.index = palette.len() as u8;
+                node.index = palette.len() as u16;
                 palette.push(Rgb::from(array::from_fn(|i| {
                     (node.rgb[i] / node.count) as u8
                 })));
@@ -266,7 +274,7 @@ pub struct OctreeQuantizer {}
 
 impl Quantizer for OctreeQuantizer {
     fn quantize(img: &RgbImage, color_count: usize) -> Palette {
-        let color_count = color_count.min(MAX_COLORS);
+        let color_count = color_count.min(MAX_HIGH_COLORS);
         let mut octree = Octree::new(color_count);
         for pixel in img.pixels() {
             octree.insert(*pixel);
@@ -291,4 +299,16 @@ mod tests {
         octree.insert(Rgb::from([1, 2, 200]));
         assert!(octree.leaf_count <= 3);
     }
+
+    #[test]
+    fn test_high_color_worst_case() {
+        // Every cell of the top four bits of each channel, the tree is full
+        // down to the leaves before it is reduced.
+        let img = RgbImage::from_fn(64, 64, |x, y| {
+            let cell = y * 64 + x;
+            Rgb([cell >> 8, cell >> 4, cell].map(|c| (c as u8 & 0xf) << 4 | 0x8))
+        });
+        let palette = OctreeQuantizer::quantize(&img, MAX_HIGH_COLORS);
+        assert_eq!(palette.len(), MAX_HIGH_COLORS);
+    }
 }
@@ -0,0 +1,211 @@
+use std::{
+    env,
+    io::{self, Write},
+};
+
+const ESC: u8 = 0x1b;
+/// GNU screen drops DCS strings longer than this.
+const SCREEN_CHUNK_SIZE: usize = 768;
+const DCS: &[u8] = b"\x1bP";
+const ST: &[u8] = b"\x1b\\";
+const TMUX_DCS: &[u8] = b"\x1bPtmux;";
+
+/// Wrapping of the output for terminal multiplexers, which swallow raw sixel.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Passthrough {
+    #[default]
+    None,
+    /// `ESC Ptmux; ... ESC \` with every inner `ESC` doubled
+    Tmux,
+    /// `ESC P ... ESC \` chunks of at most 768 bytes
+    Screen,
+}
+
+impl Passthrough {
+    /// Guess the multiplexer from the `TMUX` and `TERM` environment variables.
+    pub fn detect() -> Self {
+        if env::var_os("TMUX").is_some_and(|tmux| !tmux.is_empty()) {
+            Passthrough::Tmux
+        } else if env::var("TERM").is_ok_and(|term| term.starts_with("screen")) {
+            Passthrough::Screen
+        } else {
+            Passthrough::None
+        }
+    }
+}
+
+pub(crate) struct PassthroughWriter<'a, W: Write> {
+    inner: &'a mut W,
+    mode: Passthrough,
+    chunk_len: usize,
+    last: u8,
+    /// Bytes written to `inner` since its last newline.
+    line_len: usize,
+    buf: Vec<u8>,
+}
+
+/// Whether screen needs a new chunk before `byte`, so that an inner `ESC \`
+/// never ends the screen DCS early.
+fn is_screen_split(last: u8, byte: u8) -> bool {
+    last == ESC && byte == b'\\'
+}
+
+impl<'a, W: Write> PassthroughWriter<'a, W> {
+    pub(crate) fn new(inner: &'a mut W, mode: Passthrough) -> io::Result<Self> {
+        let header = match mode {
+            Passthrough::None => &[][..],
+            Passthrough::Tmux => TMUX_DCS,
+            Passthrough::Screen => DCS,
+        };
+        inner.write_all(header)?;
+        Ok(Self {
+            inner,
+            mode,
+            chunk_len: 0,
+            last: 0,
+            line_len: header.len(),
+            buf: Vec::new(),
+        })
+    }
+
+    pub(crate) fn line_len(&self) -> usize {
+        self.line_len
+    }
+
+    /// Number of bytes `buf` adds to the current line of the output, up to
+    /// its first newline.
+    pub(crate) fn expanded_len(&self, buf: &[u8]) -> usize {
+        let line = match buf.iter().position(|&b| b == b'\n') {
+            Some(i) => &buf[..i],
+            None => buf,
+        };
+        match self.mode {
+            Passthrough::None => line.len(),
+            Passthrough::Tmux => line.len() + line.iter().filter(|&&b| b == ESC).count(),
+            Passthrough::Screen => {
+                let (mut chunk_len, mut last) = (self.chunk_len, self.last);
+                let mut len = line.len();
+                for &byte in line {
+                    if is_screen_split(last, byte) {
+                        len += ST.len() + DCS.len();
+                        chunk_len = 0;
+                    }
+                    chunk_len += 1;
+                    last = byte;
+                    if chunk_len == SCREEN_CHUNK_SIZE {
+                        len += ST.len() + DCS.len();
+                        chunk_len = 0;
+                        last = 0;
+                    }
+                }
+                len
+            }
+        }
+    }
+
+    pub(crate) fn finish(self) -> io::Result<()> {
+        match self.mode {
+            Passthrough::None => Ok(()),
+            Passthrough::Tmux | Passthrough::Screen => self.inner.write_all(ST),
+        }
+    }
+}
+
+impl<W: Write> Write for PassthroughWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let out = match self.mode {
+            Passthrough::None => buf,
+            Passthrough::Tmux => {
+                self.buf.clear();
+                for &byte in buf {
+                    if byte == ESC {
+                        self.buf.push(ESC);
+                    }
+                    self.buf.push(byte);
+                }
+                &self.buf
+            }
+            Passthrough::Screen => {
+                self.buf.clear();
+                for &byte in buf {
+                    if is_screen_split(self.last, byte) {
+                        self.buf.extend_from_slice(ST);
+                        self.buf.extend_from_slice(DCS);
+                        self.chunk_len = 0;
+                    }
+                    self.buf.push(byte);
+                    self.chunk_len += 1;
+                    self.last = byte;
+                    // Split full chunks right away, so that the split stays
+                    // on the line of the bytes before it.
+                    if self.chunk_len == SCREEN_CHUNK_SIZE {
+                        self.buf.extend_from_slice(ST);
+                        self.buf.extend_from_slice(DCS);
+                        self.chunk_len = 0;
+                        self.last = 0;
+                    }
+                }
+                &self.buf
+            }
+        };
+        self.inner.write_all(out)?;
+        match out.iter().rposition(|&b| b == b'\n') {
+            Some(i) => self.line_len = out.len() - i - 1,
+            None => self.line_len += out.len(),
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wrap(mode: Passthrough, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut w = PassthroughWriter::new(&mut out, mode).unwrap();
+        w.write_all(data).unwrap();
+        w.finish().unwrap();
+        out
+    }
+
+    #[test]
+    fn test_tmux() {
+        assert_eq!(
+            wrap(Passthrough::Tmux, b"\x1bPq#0-\x1b\\"),
+            b"\x1bPtmux;\x1b\x1bPq#0-\x1b\x1b\\\x1b\\"
+        );
+    }
+
+    #[test]
+    fn test_screen() {
+        assert_eq!(
+            wrap(Passthrough::Screen, b"\x1bPq#0-\x1b\\"),
+            b"\x1bP\x1bPq#0-\x1b\x1b\\\x1bP\\\x1b\\"
+        );
+        let data = vec![b'~'; SCREEN_CHUNK_SIZE * 2 + 1];
+        let out = wrap(Passthrough::Screen, &data);
+        assert_eq!(out.len(), data.len() + 3 * (DCS.len() + ST.len()));
+        assert!(out
+            .split(|&b| b == ESC)
+            .all(|chunk| chunk.len() <= SCREEN_CHUNK_SIZE + 1));
+    }
+
+    #[test]
+    fn test_expanded_len() {
+        for mode in [Passthrough::None, Passthrough::Tmux, Passthrough::Screen] {
+            let mut out = Vec::new();
+            let mut w = PassthroughWriter::new(&mut out, mode).unwrap();
+            for cmd in [&b"\x1bPq"[..], &[b'~'; 1000], b"\x1b\\"] {
+                let len = w.line_len() + w.expanded_len(cmd);
+                w.write_all(cmd).unwrap();
+                assert_eq!(w.line_len(), len);
+            }
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    env,
+    io::{self, IsTerminal},
+};
+
+use crate::{terminal::query_device_attributes, Passthrough};
+
+/// Device attribute reported by terminals that support sixel graphics.
+const SIXEL_ATTRIBUTE: u32 = 4;
+
+/// Terminal graphics protocols supported by the backends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Protocol {
+    Sixel,
+    Kitty,
+    Iterm2,
+    /// Unicode block characters with SGR colors
+    Text,
+    /// Plain ASCII characters without escape codes
+    Ascii,
+}
+
+impl Protocol {
+    /// Guess the protocol from the environment of the terminal emulator. If
+    /// that is inconclusive, the terminal is asked for its device attributes
+    /// and text is used only when it answers without sixel support. Output
+    /// that is not a terminal and output through a multiplexer, which would
+    /// answer the query itself, default to sixel.
+    pub fn detect() -> Self {
+        Self::select(
+            |name| env::var(name).ok(),
+            Passthrough::detect() != Passthrough::None,
+            || {
+                io::stdout()
+                    .is_terminal()
+                    .then(query_device_attributes)
+                    .flatten()
+            },
+        )
+    }
+
+    /// Protocol for the environment variables looked up by `var`, the device
+    /// attributes are only queried when the environment is inconclusive.
+    fn select(
+        var: impl Fn(&str) -> Option<String>,
+        is_multiplexed: bool,
+        device_attributes: impl FnOnce() -> Option<Vec<u32>>,
+    ) -> Self {
+        let term = var("TERM").unwrap_or_default();
+        let term_program = var("TERM_PROGRAM").unwrap_or_default();
+        if term == "dumb" {
+            Protocol::Ascii
+        } else if var("KITTY_WINDOW_ID").is_some()
+            || term == "xterm-kitty"
+            || term == "xterm-ghostty"
+            || term_program == "ghostty"
+        {
+            Protocol::Kitty
+        } else if matches!(term_program.as_str(), "iTerm.app" | "WezTerm" | "mintty") {
+            Protocol::Iterm2
+        } else {
+            let is_sixel_term = var("KONSOLE_VERSION").is_some()
+                || var("WT_SESSION").is_some()
+                || term.contains("sixel")
+                || ["foot", "mlterm", "yaft", "contour"]
+                    .iter()
+                    .any(|prefix| term.starts_with(prefix));
+            let is_sixel_missing = !is_sixel_term
+                && !is_multiplexed
+                && device_attributes()
+                    .is_some_and(|attributes| !attributes.contains(&SIXEL_ATTRIBUTE));
+            if is_sixel_missing {
+                Protocol::Text
+            } else {
+                Protocol::Sixel
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select() {
+        let select = |vars: &[(&str, &str)], is_multiplexed, attributes: Option<&[u32]>| {
+            Protocol::select(
+                |name| {
+                    vars.iter()
+                        .find(|(key, _)| *key == name)
+                        .map(|(_, value)| value.to_string())
+                },
+                is_multiplexed,
+                || attributes.map(<[u32]>::to_vec),
+            )
+        };
+        assert_eq!(select(&[("TERM", "dumb")], false, None), Protocol::Ascii);
+        assert_eq!(
+            select(&[("TERM", "xterm-kitty")], false, None),
+            Protocol::Kitty
+        );
+        assert_eq!(
+            select(&[("TERM_PROGRAM", "WezTerm")], false, None),
+            Protocol::Iterm2
+        );
+        assert_eq!(
+            select(&[("TERM", "foot")], false, Some(&[1])),
+            Protocol::Sixel
+        );
+        assert_eq!(
+            select(&[("TERM", "xterm")], false, Some(&[62, 4, 9])),
+            Protocol::Sixel
+        );
+        assert_eq!(
+            select(&[("TERM", "xterm")], false, Some(&[1, 2])),
+            Protocol::Text
+        );
+        assert_eq!(select(&[("TERM", "xterm")], false, None), Protocol::Sixel);
+
+        // A multiplexer would answer the query itself, so it isn't sent.
+        let tmux = |name: &str| (name == "TERM").then(|| "tmux-256color".to_string());
+        assert_eq!(
+            Protocol::select(tmux, true, || unreachable!()),
+            Protocol::Sixel
+        );
+    }
+}
@@ -1,16 +1,15 @@
-use std::array;
 use std::fmt;
 
-pub struct Queue<T, const N: usize>
+pub struct Queue<T>
 where
     T: Default + Sized + Copy,
 {
-    data: [T; N],
+    data: Box<[T]>,
     start: usize,
     size: usize,
 }
 
-impl<T, const N: usize> fmt::Debug for Queue<T, N>
+impl<T> fmt::Debug for Queue<T>
 where
     T: Default + Sized + Copy + fmt::Debug,
 {
@@ -23,23 +22,24 @@ where
     }
 }
 
-impl<T, const N: usize> Queue<T, N>
+impl<T> Queue<T>
 where
     T: Default + Sized + Copy,
 {
-    pub fn new() -> Self {
+    pub fn new(capacity: usize) -> Self {
         Self {
-            data: array::from_fn(|_| Default::default()),
+            data: vec![T::default(); capacity].into_boxed_slice(),
             start: 0,
             size: 0,
         }
     }
 
     pub fn push(&mut self, value: T) {
-        if self.size == N {
-            panic!("Queue max size - {N} is exceeded");
+        let capacity = self.data.len();
+        if self.size == capacity {
+            panic!("Queue max size - {capacity} is exceeded");
         }
-        self.data[(self.start + self.size) % N] = value;
+        self.data[(self.start + self.size) % capacity] = value;
         self.size += 1;
     }
 
@@ -47,7 +47,7 @@ where
         (self.size > 0).then(|| {
             let value = self.data[self.start];
             self.start += 1;
-            if self.start == N {
+            if self.start == self.data.len() {
                 self.start = 0;
             }
             self.size -= 1;
@@ -62,7 +62,7 @@ mod tests {
 
     #[test]
     fn test_queue() {
-        let mut queue = Queue::<_, 3>::new();
+        let mut queue = Queue::new(3);
         queue.push(1);
         queue.push(2);
         queue.push(3);
@@ -93,7 +93,7 @@ mod tests {
     #[test]
     #[should_panic]
     fn test_queue_panic() {
-        let mut queue = Queue::<_, 0>::new();
+        let mut queue = Queue::new(0);
         assert_eq!(queue.pop(), None);
         queue.push(1);
     }
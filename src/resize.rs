@@ -0,0 +1,43 @@
+use image::{imageops, imageops::FilterType, RgbImage};
+use std::borrow::Cow;
+
+/// Scale `img` down to fit into `max_width` x `max_height` pixels keeping its
+/// proportions, shared by all backends. Images are never scaled up.
+pub fn fit_image(
+    img: &RgbImage,
+    max_width: Option<u32>,
+    max_height: Option<u32>,
+) -> Cow<'_, RgbImage> {
+    let (width, height) = img.dimensions();
+    let max_width = max_width.unwrap_or(width).clamp(1, width.max(1));
+    let max_height = max_height.unwrap_or(height).clamp(1, height.max(1));
+    if width <= max_width && height <= max_height {
+        return Cow::Borrowed(img);
+    }
+    let scale = f64::min(
+        max_width as f64 / width as f64,
+        max_height as f64 / height as f64,
+    );
+    let new_width = ((width as f64 * scale).round() as u32).max(1);
+    let new_height = ((height as f64 * scale).round() as u32).max(1);
+    Cow::Owned(imageops::resize(
+        img,
+        new_width,
+        new_height,
+        FilterType::Triangle,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit_image() {
+        let img = RgbImage::new(300, 200);
+        assert!(matches!(fit_image(&img, None, None), Cow::Borrowed(_)));
+        assert!(matches!(fit_image(&img, Some(400), None), Cow::Borrowed(_)));
+        assert_eq!(fit_image(&img, Some(150), None).dimensions(), (150, 100));
+        assert_eq!(fit_image(&img, Some(150), Some(50)).dimensions(), (75, 50));
+    }
+}
@@ -0,0 +1,46 @@
+/// Tracks which colors the terminal currently holds in its sixel color
+/// registers, so that consecutive images only redefine changed registers.
+///
+/// This only helps on terminals that share color registers between images
+/// (e.g. xterm with `privateColorRegisters` disabled).
+#[derive(Debug, Default)]
+pub struct Session {
+    registers: Vec<Option<[u16; 3]>>,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Forget all known registers, e.g. after the terminal was reset.
+    pub fn reset(&mut self) {
+        self.registers.clear();
+    }
+
+    /// Records `rgb` in `register`, returns `true` if it has to be defined.
+    pub(crate) fn set(&mut self, register: usize, rgb: [u16; 3]) -> bool {
+        if self.registers.len() <= register {
+            self.registers.resize(register + 1, None);
+        }
+        let is_changed = self.registers[register] != Some(rgb);
+        self.registers[register] = Some(rgb);
+        is_changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session() {
+        let mut session = Session::new();
+        assert!(session.set(0, [100, 0, 0]));
+        assert!(!session.set(0, [100, 0, 0]));
+        assert!(session.set(0, [0, 100, 0]));
+        assert!(session.set(1, [0, 100, 0]));
+        session.reset();
+        assert!(session.set(1, [0, 100, 0]));
+    }
+}
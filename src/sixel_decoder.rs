@@ -0,0 +1,320 @@
+use anyhow::Result;
+use image::{Rgb, Rgba, RgbaImage};
+
+use crate::{Color, MAX_HIGH_COLORS};
+
+const SIXEL_SIZE: usize = 6;
+const SIXEL_OFFSET: u8 = 63;
+
+/// Limit of the decoded image size in pixels, protects against bogus sizes.
+const MAX_DIMENSION: usize = 1 << 14;
+
+/// VT340 default color map in percent.
+const DEFAULT_COLORS: [[u32; 3]; 16] = [
+    [0, 0, 0],
+    [20, 20, 80],
+    [80, 13, 13],
+    [20, 80, 20],
+    [80, 20, 80],
+    [20, 80, 80],
+    [80, 80, 20],
+    [53, 53, 53],
+    [26, 26, 26],
+    [33, 33, 60],
+    [60, 26, 26],
+    [33, 60, 33],
+    [60, 33, 60],
+    [33, 60, 60],
+    [60, 60, 33],
+    [80, 80, 80],
+];
+
+fn percent_to_u8(value: u32) -> u8 {
+    ((value.min(100) * 255 + 50) / 100) as u8
+}
+
+fn rgb_color([r, g, b]: [u32; 3]) -> Color {
+    Rgb([r, g, b].map(percent_to_u8))
+}
+
+/// DEC HLS color, the hue starts at blue and goes through red and green.
+fn hls_color(hue: u32, lightness: u32, saturation: u32) -> Color {
+    let hue = ((hue + 240) % 360) as f32 / 60.0;
+    let lightness = lightness.min(100) as f32 / 100.0;
+    let saturation = saturation.min(100) as f32 / 100.0;
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+    let (r, g, b) = match hue as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = lightness - chroma / 2.0;
+    Rgb([r, g, b].map(|c| ((c + m) * 255.0).round() as u8))
+}
+
+/// Image being drawn, grows only as far as sixels are actually drawn so that
+/// bogus raster attributes can't make it allocate huge buffers.
+struct Canvas {
+    width: usize,
+    height: usize,
+    /// RGBA bytes of `width` x `height` pixels.
+    pixels: Vec<u8>,
+    background: [u8; 4],
+    extent: (usize, usize),
+}
+
+impl Canvas {
+    fn new(background: Rgba<u8>) -> Self {
+        Self {
+            width: 0,
+            height: 0,
+            pixels: Vec::new(),
+            background: background.0,
+            extent: (0, 0),
+        }
+    }
+
+    fn reserve(&mut self, width: usize, height: usize) {
+        if width <= self.width && height <= self.height {
+            return;
+        }
+        let new_width = if width > self.width {
+            width.max(self.width * 2).min(MAX_DIMENSION)
+        } else {
+            self.width
+        };
+        let new_height = if height > self.height {
+            height.max(self.height * 2).min(MAX_DIMENSION)
+        } else {
+            self.height
+        };
+        if new_width == self.width {
+            let rows = new_height - self.height;
+            self.pixels.extend(self.background.repeat(new_width * rows));
+        } else {
+            let mut pixels = self.background.repeat(new_width * new_height);
+            for (src, dst) in self
+                .pixels
+                .chunks_exact(self.width.max(1) * 4)
+                .zip(pixels.chunks_exact_mut(new_width * 4))
+            {
+                dst[..src.len()].copy_from_slice(src);
+            }
+            self.pixels = pixels;
+        }
+        self.width = new_width;
+        self.height = new_height;
+    }
+
+    fn draw(&mut self, x: usize, y: usize, sixel: u8, count: usize, color: Rgba<u8>) {
+        let rows = (u8::BITS - sixel.leading_zeros()) as usize;
+        let count = count.min(MAX_DIMENSION.saturating_sub(x));
+        if sixel == 0 || count == 0 || y + rows > MAX_DIMENSION {
+            return;
+        }
+        self.reserve(x + count, y + rows);
+        for bit in 0..rows {
+            if sixel & (1 << bit) != 0 {
+                let start = ((y + bit) * self.width + x) * 4;
+                for pixel in self.pixels[start..start + count * 4].chunks_exact_mut(4) {
+                    pixel.copy_from_slice(&color.0);
+                }
+            }
+        }
+        self.extent = (self.extent.0.max(x + count), self.extent.1.max(y + rows));
+    }
+
+    /// Image of the drawn area, the rows are compacted in place.
+    fn into_image(mut self) -> RgbaImage {
+        let (width, height) = self.extent;
+        if width < self.width {
+            for y in 1..height {
+                let start = y * self.width * 4;
+                self.pixels
+                    .copy_within(start..start + width * 4, y * width * 4);
+            }
+        }
+        self.pixels.truncate(width * height * 4);
+        RgbaImage::from_raw(width as u32, height as u32, self.pixels)
+            .expect("buffer has the size of the drawn area")
+    }
+}
+
+/// Read a `;` separated list of numbers, empty values are 0.
+fn parse_params(data: &[u8], pos: &mut usize) -> Vec<u32> {
+    let mut params = vec![0u32];
+    while let Some(&byte) = data.get(*pos) {
+        match byte {
+            b'0'..=b'9' => {
+                let last = params.last_mut().unwrap();
+                *last = last.saturating_mul(10).saturating_add((byte - b'0') as u32);
+            }
+            b';' => params.push(0),
+            _ => break,
+        }
+        *pos += 1;
+    }
+    params
+}
+
+/// Sixel parser turning every DCS image of a stream into an RGBA image.
+///
+/// Color registers are kept between images like a terminal does, so streams
+/// written with a [`Session`](crate::Session) decode correctly. Pixels that
+/// are not drawn are transparent when the image selects a transparent
+/// background (P2 = 1) and black otherwise.
+pub struct SixelDecoder {
+    registers: Vec<Color>,
+}
+
+impl Default for SixelDecoder {
+    fn default() -> Self {
+        let mut registers = vec![Rgb([0, 0, 0]); MAX_HIGH_COLORS];
+        for (register, color) in registers.iter_mut().zip(DEFAULT_COLORS) {
+            *register = rgb_color(color);
+        }
+        Self { registers }
+    }
+}
+
+impl SixelDecoder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Decode all sixel images found in `data`, other bytes are skipped.
+    pub fn decode(&mut self, data: &[u8]) -> Result<Vec<RgbaImage>> {
+        let mut images = Vec::new();
+        let mut pos = 0;
+        while pos < data.len() {
+            let byte = data[pos];
+            pos += 1;
+            let is_dcs = match byte {
+                0x90 => true,
+                0x1b if data.get(pos) == Some(&b'P') => {
+                    pos += 1;
+                    true
+                }
+                _ => false,
+            };
+            if !is_dcs {
+                continue;
+            }
+            let params = parse_params(data, &mut pos);
+            if data.get(pos) == Some(&b'q') {
+                pos += 1;
+                images.push(self.decode_image(data, &mut pos, &params));
+            }
+        }
+        Ok(images)
+    }
+
+    fn decode_image(&mut self, data: &[u8], pos: &mut usize, params: &[u32]) -> RgbaImage {
+        let background = if params.get(1) == Some(&1) {
+            Rgba([0, 0, 0, 0])
+        } else {
+            Rgba([0, 0, 0, 255])
+        };
+        let mut canvas = Canvas::new(background);
+        let (mut x, mut y) = (0, 0);
+        let mut color = 0;
+        while let Some(&byte) = data.get(*pos) {
+            *pos += 1;
+            match byte {
+                // Raster attributes, the image size follows the drawn sixels.
+                b'"' => {
+                    parse_params(data, pos);
+                }
+                b'#' => {
+                    let params = parse_params(data, pos);
+                    color = params[0] as usize % MAX_HIGH_COLORS;
+                    match params[..] {
+                        [_, 1, h, l, s, ..] => self.registers[color] = hls_color(h, l, s),
+                        [_, 2, r, g, b, ..] => self.registers[color] = rgb_color([r, g, b]),
+                        _ => {}
+                    }
+                }
+                b'!' => {
+                    let count = parse_params(data, pos)[0] as usize;
+                    if let Some(&sixel @ b'?'..=b'~') = data.get(*pos) {
+                        *pos += 1;
+                        let Rgb([r, g, b]) = self.registers[color];
+                        canvas.draw(x, y, sixel - SIXEL_OFFSET, count, Rgba([r, g, b, 255]));
+                        x += count;
+                    }
+                }
+                b'?'..=b'~' => {
+                    let Rgb([r, g, b]) = self.registers[color];
+                    canvas.draw(x, y, byte - SIXEL_OFFSET, 1, Rgba([r, g, b, 255]));
+                    x += 1;
+                }
+                b'$' => x = 0,
+                b'-' => {
+                    x = 0;
+                    y += SIXEL_SIZE;
+                }
+                // String terminator, BEL or the start of another sequence.
+                0x1b | 0x9c | 0x07 => break,
+                _ => {}
+            }
+        }
+        canvas.into_image()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EncoderBuilder, OctreeQuantizer, Session};
+    use image::RgbImage;
+
+    fn test_image() -> RgbImage {
+        RgbImage::from_fn(13, 9, |x, y| match (x + y) % 3 {
+            0 => Rgb([255, 0, 0]),
+            1 => Rgb([0, 0, 255]),
+            _ => Rgb([255, 255, 255]),
+        })
+    }
+
+    #[test]
+    fn test_decode_encoded() {
+        let img = test_image();
+        let mut encoder = EncoderBuilder::<OctreeQuantizer>::new().build();
+        let mut data = Vec::new();
+        let mut session = Session::new();
+        encoder
+            .encode_with_session(&mut data, &img, &mut session)
+            .unwrap();
+        encoder
+            .encode_with_session(&mut data, &img, &mut session)
+            .unwrap();
+        let images = SixelDecoder::new().decode(&data).unwrap();
+        assert_eq!(images.len(), 2);
+        for decoded in images {
+            assert_eq!(image::DynamicImage::from(decoded).to_rgb8(), img);
+        }
+    }
+
+    #[test]
+    fn test_decode_commands() {
+        let data = b"\x1bP0;1q#1;1;120;50;100#2;2;0;100;0#1!3~$#2@-#0A\x07";
+        let images = SixelDecoder::new().decode(data).unwrap();
+        assert_eq!(images.len(), 1);
+        let img = &images[0];
+        assert_eq!(img.dimensions(), (3, 8));
+        assert_eq!(*img.get_pixel(0, 0), Rgba([0, 255, 0, 255]));
+        assert_eq!(*img.get_pixel(2, 5), Rgba([255, 0, 0, 255]));
+        assert_eq!(*img.get_pixel(0, 7), Rgba([0, 0, 0, 255]));
+        assert_eq!(*img.get_pixel(1, 7), Rgba([0, 0, 0, 0]));
+
+        // The raster attributes don't allocate, only drawn sixels do.
+        let images = SixelDecoder::new()
+            .decode(b"\x1bPq\"1;1;16384;16384#0~\x1b\\")
+            .unwrap();
+        assert_eq!(images[0].dimensions(), (1, 6));
+    }
+}
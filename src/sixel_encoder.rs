@@ -1,58 +1,411 @@
 // use crate::median_cut::ColorQuantizer;
-use anyhow::Result;
+use anyhow::{bail, Result};
 use image::{
-    imageops::{dither, ColorMap},
-    ImageReader, RgbImage,
+    codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder},
+    imageops::{self, dither, ColorMap, FilterType},
+    AnimationDecoder, DynamicImage, ImageDecoder, ImageFormat, ImageReader, Rgb, RgbImage, Rgba,
 };
 use itertools::chain;
-use std::{array, io::Write, marker::PhantomData, path::Path};
+use log::debug;
+use rustc_hash::FxHashMap;
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader, Cursor, Read, Seek, Write},
+    marker::PhantomData,
+    mem,
+    path::Path,
+    time::{Duration, Instant},
+};
 
-use crate::{Palette, Quantizer, MAX_COLORS};
+use crate::{
+    passthrough::{Passthrough, PassthroughWriter},
+    Adjustments, Backend, Color, Palette, Quantizer, Session, MAX_COLORS, MAX_HIGH_COLORS,
+};
+#[cfg(feature = "parallel")]
+use {image::Pixel, rayon::prelude::*};
 
 const SIXEL_SIZE: u8 = 6;
 const SIXEL_OFFSET: u8 = 63;
-const SIXEL_ESC: char = '\x1b';
+
+/// Control sequence introducer used for the sixel DCS.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Introducer {
+    /// `ESC P` and `ESC \`
+    #[default]
+    #[cfg_attr(feature = "cli", value(name = "7bit"))]
+    SevenBit,
+    /// C1 `DCS` (`0x90`) and `ST` (`0x9C`)
+    #[cfg_attr(feature = "cli", value(name = "8bit"))]
+    EightBit,
+}
+
+impl Introducer {
+    fn dcs(self) -> &'static [u8] {
+        match self {
+            Introducer::SevenBit => b"\x1bP",
+            Introducer::EightBit => b"\x90",
+        }
+    }
+
+    fn st(self) -> &'static [u8] {
+        match self {
+            Introducer::SevenBit => b"\x1b\\",
+            Introducer::EightBit => b"\x9c",
+        }
+    }
+}
+
+/// Sequence terminating the sixel DCS.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Terminator {
+    /// String terminator matching the [`Introducer`]
+    #[default]
+    St,
+    /// `BEL` (`0x07`), for terminals that misbehave on `ST`
+    Bel,
+}
+
+/// Output options for sixel printers like the DEC LA50 and LA75.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PrinterOptions {
+    /// Horizontal grid size `P3` in decipoints (1/720 inch), `0` selects
+    /// the printer default.
+    pub grid_size: u32,
+    /// Print every pixel as 2x2 dots.
+    pub pixel_doubling: bool,
+    /// Wrap the output in `CSI 5 i` and `CSI 4 i` (printer controller mode),
+    /// so that the terminal forwards it to the attached printer.
+    pub controller_mode: bool,
+}
+
+/// Cursor handling around the image.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CursorOptions {
+    /// Save the cursor before the image and restore it afterwards
+    /// (`ESC 7` / `ESC 8`).
+    pub save_restore: bool,
+    /// Draw the image at the given one-based cell `(row, col)` (`CUP`).
+    pub position: Option<(u32, u32)>,
+    /// Start a new line after the image, so that following text is placed
+    /// below it regardless of where the terminal left the cursor. When the
+    /// cursor is back at the top of the image because of `save_restore` or
+    /// `display_mode`, it moves down by the image height in cells, or to the
+    /// row below the image if it was drawn at `position`.
+    pub move_below: bool,
+    /// Enable sixel display mode (`DECSDM`) for the image and disable it
+    /// afterwards. The image is then drawn without scrolling.
+    pub display_mode: bool,
+    /// Height of a terminal cell in pixels, required by `move_below` together
+    /// with `save_restore` or `display_mode`.
+    pub cell_height: u32,
+}
+
+impl CursorOptions {
+    /// Whether the cursor is left at the top of the image.
+    fn is_restored(&self) -> bool {
+        self.save_restore || self.display_mode
+    }
+}
+
+/// Composite the image over `background` if it has an alpha channel.
+fn flatten(img: DynamicImage, background: Color) -> RgbImage {
+    if !img.color().has_alpha() {
+        return img.to_rgb8();
+    }
+    let img = img.to_rgba8();
+    RgbImage::from_fn(img.width(), img.height(), |x, y| {
+        let Rgba(color) = *img.get_pixel(x, y);
+        let alpha = color[3] as u32;
+        Rgb(std::array::from_fn(|i| {
+            let blended = color[i] as u32 * alpha + background[i] as u32 * (255 - alpha);
+            ((blended + 127) / 255) as u8
+        }))
+    })
+}
+
+/// Decode an image turned upright according to its EXIF orientation.
+fn decode_oriented<R: BufRead + Seek>(
+    reader: ImageReader<R>,
+    background: Color,
+) -> Result<RgbImage> {
+    let mut decoder = reader.into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut img = DynamicImage::from_decoder(decoder)?;
+    img.apply_orientation(orientation);
+    Ok(flatten(img, background))
+}
+
+/// Open an image, transparent pixels are composited over `background`.
+pub fn open_image(img_path: &Path, background: Color) -> Result<RgbImage> {
+    decode_oriented(ImageReader::open(img_path)?, background)
+}
+
+/// Read an image from `reader`, the format is guessed from its content.
+pub fn read_image<R: Read>(mut reader: R, background: Color) -> Result<RgbImage> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    decode_oriented(
+        ImageReader::new(Cursor::new(data)).with_guessed_format()?,
+        background,
+    )
+}
+
+/// Decode every frame of an animated GIF, PNG or WebP image with its delay,
+/// other images are returned as a single frame. Frames are composited over
+/// `background`.
+pub fn open_animation(img_path: &Path, background: Color) -> Result<Vec<(RgbImage, Duration)>> {
+    let reader = ImageReader::new(BufReader::new(File::open(img_path)?)).with_guessed_format()?;
+    let frames = match reader.format() {
+        Some(ImageFormat::Gif) => GifDecoder::new(reader.into_inner())?.into_frames(),
+        Some(ImageFormat::Png) => {
+            let decoder = PngDecoder::new(reader.into_inner())?;
+            if !decoder.is_apng()? {
+                return Ok(vec![(open_image(img_path, background)?, Duration::ZERO)]);
+            }
+            decoder.apng()?.into_frames()
+        }
+        Some(ImageFormat::WebP) => {
+            let decoder = WebPDecoder::new(reader.into_inner())?;
+            if !decoder.has_animation() {
+                return Ok(vec![(open_image(img_path, background)?, Duration::ZERO)]);
+            }
+            decoder.into_frames()
+        }
+        _ => return Ok(vec![(open_image(img_path, background)?, Duration::ZERO)]),
+    };
+    frames
+        .map(|frame| {
+            let frame = frame?;
+            let delay = Duration::from(frame.delay());
+            Ok((flatten(frame.into_buffer().into(), background), delay))
+        })
+        .collect()
+}
 
 #[derive(Debug)]
-pub struct EncoderBuilder<'a, E: Quantizer> {
-    img_path: &'a Path,
+pub struct EncoderBuilder<E: Quantizer> {
+    palette_size: usize,
+    registers: usize,
+    bands_per_palette: usize,
+    dither: bool,
+    optimize: bool,
+    introducer: Introducer,
+    terminator: Terminator,
+    passthrough: Passthrough,
+    pixel_aspect: (u32, u32),
+    aspect_macro: bool,
+    raster_attributes: bool,
+    printer: Option<PrinterOptions>,
+    line_width: usize,
+    cursor: CursorOptions,
     debug: bool,
+    palette: Vec<Color>,
+    adjustments: Adjustments,
     _q: PhantomData<E>,
 }
 
-impl<'a, E: Quantizer> EncoderBuilder<'a, E> {
-    pub fn new(img_path: &'a Path) -> Self {
+impl<E: Quantizer> Default for EncoderBuilder<E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E: Quantizer> EncoderBuilder<E> {
+    pub fn new() -> Self {
         Self {
-            img_path,
+            palette_size: MAX_COLORS,
+            registers: MAX_COLORS,
+            bands_per_palette: 0,
+            dither: false,
+            optimize: true,
+            introducer: Introducer::SevenBit,
+            terminator: Terminator::St,
+            passthrough: Passthrough::None,
+            pixel_aspect: (1, 1),
+            aspect_macro: false,
+            raster_attributes: true,
+            printer: None,
+            line_width: 0,
+            cursor: CursorOptions::default(),
             debug: false,
+            palette: Vec::new(),
+            adjustments: Adjustments::default(),
             _q: Default::default(),
         }
     }
 
+    pub fn palette_size(mut self, palette_size: usize) -> Self {
+        self.palette_size = palette_size;
+        self
+    }
+
+    /// Number of color registers supported by the terminal, the palette size
+    /// is limited by it. Values above [`MAX_COLORS`] enable high-color mode
+    /// for terminals like mlterm, foot or WezTerm, up to [`MAX_HIGH_COLORS`].
+    pub fn registers(mut self, registers: usize) -> Self {
+        self.registers = registers.clamp(1, MAX_HIGH_COLORS);
+        self
+    }
+
+    /// Quantize every group of `bands` six pixel high bands separately and
+    /// redefine the color registers before drawing it, which preserves local
+    /// gradients. Requires a terminal that resolves register colors at draw
+    /// time. `0` uses a single palette for the whole image (default).
+    pub fn adaptive_palette(mut self, bands: usize) -> Self {
+        self.bands_per_palette = bands;
+        self
+    }
+
+    pub fn dither(mut self, is_dither: bool) -> Self {
+        self.dither = is_dither;
+        self
+    }
+
+    /// Reorder colors per band, trim trailing empty sixels and write short
+    /// runs literally. Enabled by default.
+    pub fn optimize(mut self, is_optimize: bool) -> Self {
+        self.optimize = is_optimize;
+        self
+    }
+
+    /// 8-bit introducers produce output that is not valid UTF-8, so
+    /// [`SixelEncoder::encode_to_string`] fails with them.
+    pub fn introducer(mut self, introducer: Introducer) -> Self {
+        self.introducer = introducer;
+        self
+    }
+
+    pub fn terminator(mut self, terminator: Terminator) -> Self {
+        self.terminator = terminator;
+        self
+    }
+
+    /// Wrap the output for tmux or GNU screen, see [`Passthrough::detect`].
+    pub fn passthrough(mut self, passthrough: Passthrough) -> Self {
+        self.passthrough = passthrough;
+        self
+    }
+
+    /// Pixel aspect ratio as vertical `pan` to horizontal `pad` units, e.g.
+    /// `(2, 1)` for VT340 compatible output. The image is scaled down along
+    /// the longer pixel side so that it keeps its proportions.
+    pub fn pixel_aspect(mut self, pan: u32, pad: u32) -> Self {
+        self.pixel_aspect = (pan.max(1), pad.max(1));
+        self
+    }
+
+    /// Also select the pixel aspect ratio with the `P1` DCS parameter,
+    /// rounded to the closest of 1:1, 2:1, 3:1 and 5:1.
+    pub fn aspect_macro(mut self, is_aspect_macro: bool) -> Self {
+        self.aspect_macro = is_aspect_macro;
+        self
+    }
+
+    /// Write the `"Pan;Pad;Ph;Pv` raster attributes. Enabled by default,
+    /// disable for terminals that mis-parse them.
+    pub fn raster_attributes(mut self, is_raster_attributes: bool) -> Self {
+        self.raster_attributes = is_raster_attributes;
+        self
+    }
+
+    /// Produce output for a sixel printer instead of a terminal.
+    pub fn printer(mut self, printer: PrinterOptions) -> Self {
+        self.printer = Some(printer);
+        self
+    }
+
+    /// Insert newlines so that no output line is longer than `line_width`
+    /// bytes, which sixel parsers ignore. Newlines are only placed between
+    /// commands, so `!` repeats and color definitions are never split. `0`
+    /// disables wrapping (default).
+    pub fn line_width(mut self, line_width: usize) -> Self {
+        self.line_width = line_width;
+        self
+    }
+
+    pub fn cursor(mut self, cursor: CursorOptions) -> Self {
+        self.cursor = cursor;
+        self
+    }
+
     pub fn debug(mut self, is_debug: bool) -> Self {
         self.debug = is_debug;
         self
     }
 
-    pub fn build(self) -> Result<SixelEncoder<E>> {
-        Ok(SixelEncoder {
-            rgb8_img: ImageReader::open(self.img_path)?.decode()?.to_rgb8(),
+    /// Map images to these colors instead of quantizing them, e.g. to share
+    /// one palette between several images. Limited by the number of
+    /// registers, an empty palette enables quantization again.
+    pub fn palette(mut self, colors: &[Color]) -> Self {
+        self.palette = colors.to_vec();
+        self
+    }
+
+    /// Correct the colors of every image before it is quantized.
+    pub fn adjustments(mut self, adjustments: Adjustments) -> Self {
+        self.adjustments = adjustments;
+        self
+    }
+
+    pub fn build(self) -> SixelEncoder<E> {
+        SixelEncoder {
+            palette_size: self.palette_size.min(self.registers),
+            bands_per_palette: self.bands_per_palette,
+            is_dither: self.dither,
+            is_optimize: self.optimize,
+            introducer: self.introducer,
+            terminator: self.terminator,
+            passthrough: self.passthrough,
+            pixel_aspect: self.pixel_aspect,
+            aspect_macro: self.aspect_macro,
+            raster_attributes: self.raster_attributes,
+            printer: self.printer,
+            line_width: self.line_width,
+            cursor: self.cursor,
             is_debug: self.debug,
+            palette: self.palette.into_iter().take(self.registers).collect(),
+            adjustments: self.adjustments,
+            dither_buf: Default::default(),
+            indices: Default::default(),
+            lines: Default::default(),
             _q: Default::default(),
-        })
+        }
     }
 }
 
 pub struct SixelEncoder<E: Quantizer> {
-    rgb8_img: RgbImage,
+    palette_size: usize,
+    bands_per_palette: usize,
+    is_dither: bool,
+    is_optimize: bool,
+    introducer: Introducer,
+    terminator: Terminator,
+    passthrough: Passthrough,
+    pixel_aspect: (u32, u32),
+    aspect_macro: bool,
+    raster_attributes: bool,
+    printer: Option<PrinterOptions>,
+    line_width: usize,
+    cursor: CursorOptions,
     is_debug: bool,
+    palette: Vec<Color>,
+    adjustments: Adjustments,
+    dither_buf: RgbImage,
+    indices: Vec<u16>,
+    lines: Vec<SixelLine>,
     _q: PhantomData<E>,
 }
 
+#[derive(Clone, Copy)]
 struct Sixel(u8, usize);
 
 enum EncoderCmd {
-    Color(u8),
+    Color(u16),
     Sixel(Sixel),
     MoveToBegining,
     MoveToNextLine,
@@ -60,12 +413,25 @@ enum EncoderCmd {
 
 #[derive(Default)]
 struct SixelLineItem {
-    color: u8,
+    color: u16,
     sixels: Vec<Sixel>,
     count: usize,
+    /// Drawn right after the previous color instead of from the line start.
+    is_chained: bool,
 }
 
 impl SixelLineItem {
+    fn offset(&self) -> usize {
+        match self.sixels.first() {
+            Some(Sixel(0, count)) => *count,
+            _ => 0,
+        }
+    }
+
+    fn end(&self) -> usize {
+        self.sixels.iter().map(|Sixel(_, count)| count).sum()
+    }
+
     fn push(&mut self, sixel: u8, sixel_idx: usize) {
         match self.sixels.pop() {
             Some(Sixel(current_sixel, count)) => {
@@ -102,28 +468,94 @@ impl SixelLineItem {
     }
 }
 
+#[derive(Default)]
 struct SixelLine {
-    indices: [Option<usize>; MAX_COLORS],
+    indices: Vec<Option<usize>>,
     count: usize,
-    colors: [SixelLineItem; MAX_COLORS],
+    colors: Vec<SixelLineItem>,
 }
 
-impl Default for SixelLine {
-    fn default() -> Self {
-        Self {
-            indices: [None; MAX_COLORS],
-            count: 0,
-            colors: array::from_fn(|_| Default::default()),
+impl SixelLine {
+    fn clear(&mut self) {
+        for color in self.colors[0..self.count].iter_mut() {
+            self.indices[color.color as usize] = None;
+            color.sixels.clear();
+            color.count = 0;
+            color.is_chained = false;
         }
+        self.count = 0;
     }
-}
 
-impl SixelLine {
-    fn push(&mut self, color: u8, sixel: u8, sixel_idx: usize) {
+    fn fill(&mut self, indices: &[u16], width: usize, height: usize, y: usize) {
+        self.clear();
+        for (i, sixels) in (0..width)
+            .map(|x| (y..height).map(move |y| indices[y * width + x]))
+            .map(get_sixels_map)
+            .enumerate()
+        {
+            for (color, sixel) in sixels {
+                self.push(color, sixel, i);
+            }
+            self.align(i);
+        }
+        debug_assert!(self
+            .colors
+            .iter()
+            .take(self.count)
+            .all(|color| color.count == width));
+    }
+
+    /// Empty sixels at the end of a color are never drawn, since `$` or `-`
+    /// always follows them, so they are dropped. A color that starts after
+    /// the previous one ends is chained to it: it continues from there with
+    /// a gap of empty sixels instead of `$` and its full lead.
+    fn optimize(&mut self) {
+        let colors = &mut self.colors[0..self.count];
+        for color in colors.iter_mut() {
+            while let Some(Sixel(0, _)) = color.sixels.last() {
+                color.sixels.pop();
+            }
+        }
+        colors.sort_by_key(|color| (color.offset(), color.color));
+
+        // Each pass from the line start takes the first remaining color that
+        // fits after the current position, keeping the rest in order.
+        let mut start = 0;
+        while start < colors.len() {
+            let mut end = colors[start].end();
+            start += 1;
+            let mut i = start;
+            while i < colors.len() {
+                let offset = colors[i].offset();
+                if offset >= end {
+                    colors[start..=i].rotate_right(1);
+                    let color = &mut colors[start];
+                    let next_end = color.end();
+                    if offset == end {
+                        color.sixels.remove(0);
+                    } else {
+                        color.sixels[0].1 = offset - end;
+                    }
+                    color.is_chained = true;
+                    end = next_end;
+                    start += 1;
+                }
+                i += 1;
+            }
+        }
+    }
+
+    fn push(&mut self, color: u16, sixel: u8, sixel_idx: usize) {
+        if self.indices.len() <= color as usize {
+            self.indices.resize(color as usize + 1, None);
+        }
         let idx = match self.indices[color as usize] {
             Some(idx) => idx,
             None => {
                 let idx = self.count;
+                if idx == self.colors.len() {
+                    self.colors.push(Default::default());
+                }
                 self.indices[color as usize] = Some(idx);
                 self.colors[idx].color = color;
                 self.count += 1;
@@ -140,126 +572,813 @@ impl SixelLine {
     }
 }
 
-fn get_sixels_map(colors: impl Iterator<Item = usize>) -> impl Iterator<Item = (u8, u8)> {
-    let mut indices = [Option::<usize>::None; MAX_COLORS];
-    let mut sixels = [Option::<(u8, u8)>::None; SIXEL_SIZE as usize];
+fn get_sixels_map(colors: impl Iterator<Item = u16>) -> impl Iterator<Item = (u16, u8)> {
+    let mut sixels = [Option::<(u16, u8)>::None; SIXEL_SIZE as usize];
     for (i, color) in colors.take(SIXEL_SIZE as usize).enumerate() {
-        let idx = match indices[color] {
-            Some(idx) => idx,
-            None => {
-                indices[color] = Some(i);
-                i
-            }
-        };
-        sixels[idx] = Some((color as u8, sixels[idx].unwrap_or_default().1 + (1 << i)));
+        let idx = sixels
+            .iter()
+            .position(|sixel| sixel.is_none_or(|(c, _)| c == color))
+            .unwrap_or_default();
+        sixels[idx] = Some((color, sixels[idx].unwrap_or_default().1 + (1 << i)));
     }
     sixels.into_iter().flatten()
 }
 
-fn get_sixel_lines(img: RgbImage, palette: Palette) -> impl Iterator<Item = SixelLine> {
-    let width = img.width();
-    let height = img.height();
-    let sixel_size = SIXEL_SIZE as u32;
-    (0..height + sixel_size - 1)
-        .step_by(sixel_size as usize)
-        .map(move |y| {
-            let (img, palette) = (&img, &palette);
-            let mut colors: SixelLine = Default::default();
-            for (i, sixels) in (0..width)
-                .map(|x| (y..height).map(move |y| palette.index_of(img.get_pixel(x, y))))
-                .map(get_sixels_map)
-                .enumerate()
-            {
-                for (color, sixel) in sixels {
-                    colors.push(color, sixel, i);
+#[cfg(not(feature = "parallel"))]
+fn fill_index_map(indices: &mut Vec<u16>, img: &RgbImage, palette: &Palette) {
+    let mut cache = FxHashMap::default();
+    indices.extend(img.pixels().map(|pixel| {
+        *cache
+            .entry(*pixel)
+            .or_insert_with(|| palette.index_of(pixel) as u16)
+    }));
+}
+
+#[cfg(feature = "parallel")]
+fn fill_index_map(indices: &mut Vec<u16>, img: &RgbImage, palette: &Palette) {
+    indices.par_extend(img.as_raw().par_chunks_exact(3).map_init(
+        FxHashMap::default,
+        |cache, pixel| {
+            let pixel = Color::from_slice(pixel);
+            *cache
+                .entry(*pixel)
+                .or_insert_with(|| palette.index_of(pixel) as u16)
+        },
+    ));
+}
+
+#[cfg(not(feature = "parallel"))]
+fn fill_sixel_lines(
+    lines: &mut [SixelLine],
+    indices: &[u16],
+    width: usize,
+    height: usize,
+    is_optimize: bool,
+) {
+    for (band, line) in lines.iter_mut().enumerate() {
+        line.fill(indices, width, height, band * SIXEL_SIZE as usize);
+        if is_optimize {
+            line.optimize();
+        }
+    }
+}
+
+#[cfg(feature = "parallel")]
+fn fill_sixel_lines(
+    lines: &mut [SixelLine],
+    indices: &[u16],
+    width: usize,
+    height: usize,
+    is_optimize: bool,
+) {
+    lines.par_iter_mut().enumerate().for_each(|(band, line)| {
+        line.fill(indices, width, height, band * SIXEL_SIZE as usize);
+        if is_optimize {
+            line.optimize();
+        }
+    });
+}
+
+fn get_encoder_cmds(line: &SixelLine) -> impl Iterator<Item = EncoderCmd> + '_ {
+    line.colors
+        .iter()
+        .take(line.count)
+        .enumerate()
+        .flat_map(move |(i, color)| {
+            chain!(
+                [EncoderCmd::Color(color.color)],
+                color.sixels.iter().copied().map(EncoderCmd::Sixel),
+                if i + 1 == line.count {
+                    Some(EncoderCmd::MoveToNextLine)
+                } else if line.colors[i + 1].is_chained {
+                    None
+                } else {
+                    Some(EncoderCmd::MoveToBegining)
                 }
-                colors.align(i);
-            }
-            debug_assert!(colors
-                .colors
-                .iter()
-                .take(colors.count)
-                .all(|color| color.count == width as usize));
-            colors
+            )
         })
 }
 
-fn get_encoder_cmds(lines: impl Iterator<Item = SixelLine>) -> impl Iterator<Item = EncoderCmd> {
-    lines.flat_map(|line| {
-        line.colors
-            .into_iter()
-            .take(line.count)
-            .enumerate()
-            .flat_map(move |(i, color)| {
-                chain!(
-                    [EncoderCmd::Color(color.color)],
-                    color.sixels.into_iter().map(EncoderCmd::Sixel),
-                    [if i == line.count.saturating_sub(1) {
-                        EncoderCmd::MoveToNextLine
-                    } else {
-                        EncoderCmd::MoveToBegining
-                    }]
-                )
-            })
-    })
+fn get_aspect_macro_param((pan, pad): (u32, u32)) -> u8 {
+    let ratio = pan as f32 / pad as f32;
+    if ratio >= 4.0 {
+        2
+    } else if ratio >= 2.5 {
+        3
+    } else if ratio >= 1.5 {
+        0
+    } else {
+        7
+    }
+}
+
+/// Statistics collected while encoding a single image.
+#[derive(Debug, Default, Clone)]
+pub struct EncodeStats {
+    /// Number of bytes written to the output.
+    pub output_bytes: usize,
+    /// Number of palette colors drawn, the most of any band's palette with
+    /// an adaptive palette.
+    pub palette_size: usize,
+    /// Number of six pixel high bands.
+    pub band_count: usize,
+    /// Number of `#` color selections emitted.
+    pub color_switches: usize,
+    /// Time spent quantizing and dithering.
+    pub quantize_time: Duration,
+    /// Time spent mapping pixels to palette indices.
+    pub map_time: Duration,
+    /// Time spent building bands and writing output.
+    pub emit_time: Duration,
+}
+
+struct CountingWriter<'a, W: Write> {
+    inner: &'a mut W,
+    count: usize,
+}
+
+impl<W: Write> Write for CountingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Treats every write as one command and starts a new line before it if
+/// the current line would get longer than `line_width`.
+struct LineWrapWriter<'a, 'b, W: Write> {
+    inner: &'a mut PassthroughWriter<'b, W>,
+    line_width: usize,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> LineWrapWriter<'_, '_, W> {
+    /// Line lengths are measured after the passthrough wrapping.
+    fn write_cmd(&mut self, cmd: &[u8]) -> io::Result<()> {
+        let len = self.inner.line_len();
+        if self.line_width > 0 && len > 0 && len + self.inner.expanded_len(cmd) > self.line_width {
+            self.inner.write_all(b"\n")?;
+        }
+        self.inner.write_all(cmd)
+    }
+}
+
+impl<W: Write> Write for LineWrapWriter<'_, '_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_cmd(buf)?;
+        Ok(buf.len())
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.write_cmd(buf)
+    }
+
+    fn write_fmt(&mut self, args: fmt::Arguments<'_>) -> io::Result<()> {
+        if let Some(cmd) = args.as_str() {
+            return self.write_cmd(cmd.as_bytes());
+        }
+        let mut buf = mem::take(&mut self.buf);
+        buf.clear();
+        buf.write_fmt(args)?;
+        let result = self.write_cmd(&buf);
+        self.buf = buf;
+        result
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
 }
 
 impl<E: Quantizer> SixelEncoder<E> {
-    pub fn image_to_sixel<W: Write>(
-        mut self,
+    pub fn encode_to_vec(&mut self, img: &RgbImage) -> Result<(Vec<u8>, EncodeStats)> {
+        let mut buf = Vec::new();
+        let stats = self.encode(&mut buf, img)?;
+        Ok((buf, stats))
+    }
+
+    pub fn encode_to_string(&mut self, img: &RgbImage) -> Result<(String, EncodeStats)> {
+        let (buf, stats) = self.encode_to_vec(img)?;
+        Ok((String::from_utf8(buf)?, stats))
+    }
+
+    pub fn encode<W: Write>(&mut self, w: &mut W, img: &RgbImage) -> Result<EncodeStats> {
+        self.encode_inner(w, img, None)
+    }
+
+    /// Same as [`SixelEncoder::encode`], but only defines color registers
+    /// whose value in the terminal differs from the palette.
+    pub fn encode_with_session<W: Write>(
+        &mut self,
         w: &mut W,
-        palette_size: usize,
-        is_dither: bool,
-    ) -> Result<()> {
-        let palette = E::quantize(&self.rgb8_img, palette_size);
-        if is_dither {
-            dither(&mut self.rgb8_img, &palette);
-        }
-        let width = self.rgb8_img.width() as usize;
-        let height = self.rgb8_img.height() as usize;
-        write!(w, "{SIXEL_ESC}Pq\"1;1;{width};{height}")?;
-        if self.is_debug {
-            writeln!(w)?;
+        img: &RgbImage,
+        session: &mut Session,
+    ) -> Result<EncodeStats> {
+        self.encode_inner(w, img, Some(session))
+    }
+
+    fn scale<'a>(&self, img: &'a RgbImage) -> Cow<'a, RgbImage> {
+        let (pan, pad) = self.pixel_aspect;
+        let img = match pan.cmp(&pad) {
+            Ordering::Equal => Cow::Borrowed(img),
+            Ordering::Greater => {
+                let height = (img.height() * pad).div_ceil(pan);
+                Cow::Owned(imageops::resize(
+                    img,
+                    img.width(),
+                    height,
+                    FilterType::Triangle,
+                ))
+            }
+            Ordering::Less => {
+                let width = (img.width() * pan).div_ceil(pad);
+                Cow::Owned(imageops::resize(
+                    img,
+                    width,
+                    img.height(),
+                    FilterType::Triangle,
+                ))
+            }
+        };
+        if self.printer.is_some_and(|printer| printer.pixel_doubling) {
+            let (width, height) = (img.width() * 2, img.height() * 2);
+            Cow::Owned(imageops::resize(&*img, width, height, FilterType::Nearest))
+        } else {
+            img
         }
-        for (i, rgb) in palette
-            .get_palette()
-            .iter()
-            .map(|color| color.0.map(|c| c as u16 * 100 / 255))
-            .enumerate()
-        {
-            write!(w, "#{i};2;{};{};{}", rgb[0], rgb[1], rgb[2])?;
+    }
+
+    fn encode_inner<W: Write>(
+        &mut self,
+        w: &mut W,
+        img: &RgbImage,
+        mut session: Option<&mut Session>,
+    ) -> Result<EncodeStats> {
+        if self.cursor.move_below && self.cursor.is_restored() && self.cursor.cell_height == 0 {
+            bail!("moving below the image after restoring the cursor requires the cell height");
+        }
+        let mut stats = EncodeStats::default();
+        let mut counter = CountingWriter { inner: w, count: 0 };
+        if self.cursor.save_restore {
+            counter.write_all(b"\x1b7")?;
+        }
+        if let Some((row, col)) = self.cursor.position {
+            write!(counter, "\x1b[{row};{col}H")?;
+        }
+        let is_controller_mode = self.printer.is_some_and(|printer| printer.controller_mode);
+        if is_controller_mode {
+            counter.write_all(b"\x1b[5i")?;
+        }
+        let mut passthrough = PassthroughWriter::new(&mut counter, self.passthrough)?;
+        if self.cursor.display_mode {
+            passthrough.write_all(b"\x1b[?80h")?;
+        }
+        let mut w = LineWrapWriter {
+            inner: &mut passthrough,
+            line_width: self.line_width,
+            buf: Vec::new(),
+        };
+
+        let scaled = self.adjustments.apply(self.scale(img));
+        let img: &RgbImage = &scaled;
+        let width = img.width() as usize;
+        let height = img.height() as usize;
+        stats.band_count = height.div_ceil(SIXEL_SIZE as usize);
+        let bands_per_palette = match self.bands_per_palette {
+            0 => stats.band_count.max(1),
+            n => n,
+        };
+
+        let mut palettes = Vec::with_capacity(stats.band_count.div_ceil(bands_per_palette));
+        self.indices.clear();
+        for y in (0..height).step_by(bands_per_palette * SIXEL_SIZE as usize) {
+            let region = if bands_per_palette >= stats.band_count {
+                Cow::Borrowed(img)
+            } else {
+                let rows = (bands_per_palette * SIXEL_SIZE as usize).min(height - y);
+                Cow::Owned(
+                    imageops::crop_imm(img, 0, y as u32, width as u32, rows as u32).to_image(),
+                )
+            };
+
+            let start = Instant::now();
+            let palette = if self.palette.is_empty() {
+                E::quantize(&region, self.palette_size)
+            } else {
+                self.palette.iter().copied().collect()
+            };
+            let region = if self.is_dither {
+                let mut buf = mem::take(&mut self.dither_buf).into_raw();
+                buf.clear();
+                buf.extend_from_slice(region.as_raw());
+                self.dither_buf = RgbImage::from_raw(region.width(), region.height(), buf)
+                    .expect("buffer has the size of the source image");
+                dither(&mut self.dither_buf, &palette);
+                &self.dither_buf
+            } else {
+                &region
+            };
+            stats.quantize_time += start.elapsed();
+
+            let start = Instant::now();
+            let region_start = self.indices.len();
+            fill_index_map(&mut self.indices, region, &palette);
+            stats.map_time += start.elapsed();
+            let mut is_used = vec![false; palette.len()];
+            for &index in &self.indices[region_start..] {
+                is_used[index as usize] = true;
+            }
+            let used = is_used.into_iter().filter(|&is_used| is_used).count();
+            stats.palette_size = stats.palette_size.max(used);
+            palettes.push(palette);
+        }
+
+        let start = Instant::now();
+        if self.lines.len() < stats.band_count {
+            self.lines.resize_with(stats.band_count, Default::default);
+        }
+        let lines = &mut self.lines[0..stats.band_count];
+        fill_sixel_lines(lines, &self.indices, width, height, self.is_optimize);
+        let mut header = self.introducer.dcs().to_vec();
+        if self.aspect_macro {
+            write!(header, "{}", get_aspect_macro_param(self.pixel_aspect))?;
+        }
+        if let Some(printer) = self.printer {
+            write!(header, ";;{}", printer.grid_size)?;
+        }
+        header.push(b'q');
+        w.write_all(&header)?;
+        if self.raster_attributes {
+            let (pan, pad) = self.pixel_aspect;
+            write!(w, "\"{pan};{pad};{width};{height}")?;
         }
         if self.is_debug {
-            writeln!(w)?
-        }
-        for cmd in get_encoder_cmds(get_sixel_lines(self.rgb8_img, palette)) {
-            match cmd {
-                EncoderCmd::Color(color) => write!(w, "#{color}"),
-                EncoderCmd::Sixel(Sixel(sixel, count)) => {
-                    let sixel = (sixel + SIXEL_OFFSET) as char;
-                    if count == 1 {
-                        write!(w, "{sixel}")
-                    } else {
-                        write!(w, "!{count}{sixel}")
-                    }
+            writeln!(w)?;
+        }
+        for (band, line) in lines.iter().enumerate() {
+            if band % bands_per_palette == 0 {
+                for (i, rgb) in palettes[band / bands_per_palette]
+                    .get_palette()
+                    .iter()
+                    .map(|color| color.0.map(|c| c as u16 * 100 / 255))
+                    .enumerate()
+                    .filter(|(i, rgb)| session.as_mut().is_none_or(|s| s.set(*i, *rgb)))
+                {
+                    write!(w, "#{i};2;{};{};{}", rgb[0], rgb[1], rgb[2])?;
                 }
-                EncoderCmd::MoveToBegining => {
-                    if self.is_debug {
-                        writeln!(w, "$")
-                    } else {
-                        write!(w, "$")
-                    }
+                if self.is_debug {
+                    writeln!(w)?
                 }
-                EncoderCmd::MoveToNextLine => {
-                    if self.is_debug {
-                        writeln!(w, "-")
-                    } else {
-                        write!(w, "-")
+            }
+            for cmd in get_encoder_cmds(line) {
+                match cmd {
+                    EncoderCmd::Color(color) => {
+                        stats.color_switches += 1;
+                        write!(w, "#{color}")
+                    }
+                    EncoderCmd::Sixel(Sixel(sixel, count)) => {
+                        let sixel = (sixel + SIXEL_OFFSET) as char;
+                        if count == 1 {
+                            write!(w, "{sixel}")
+                        } else if self.is_optimize && count <= 3 {
+                            (0..count).try_for_each(|_| write!(w, "{sixel}"))
+                        } else {
+                            write!(w, "!{count}{sixel}")
+                        }
+                    }
+                    EncoderCmd::MoveToBegining => {
+                        if self.is_debug {
+                            writeln!(w, "$")
+                        } else {
+                            write!(w, "$")
+                        }
                     }
+                    EncoderCmd::MoveToNextLine => {
+                        if self.is_debug {
+                            writeln!(w, "-")
+                        } else {
+                            write!(w, "-")
+                        }
+                    }
+                }?
+            }
+        }
+        w.write_all(match self.terminator {
+            Terminator::St => self.introducer.st(),
+            Terminator::Bel => b"\x07",
+        })?;
+        if self.cursor.display_mode {
+            passthrough.write_all(b"\x1b[?80l")?;
+        }
+        passthrough.finish()?;
+        if is_controller_mode {
+            counter.write_all(b"\x1b[4i")?;
+        }
+        if self.cursor.save_restore {
+            counter.write_all(b"\x1b8")?;
+        }
+        if self.cursor.move_below {
+            let rows = if self.cursor.is_restored() {
+                height.div_ceil(self.cursor.cell_height as usize)
+            } else {
+                1
+            };
+            match self.cursor.position {
+                // `ESC 8` went back to where the cursor was before `CUP`.
+                Some((row, _)) if self.cursor.save_restore => {
+                    write!(counter, "\x1b[{};1H", row as usize + rows)?
                 }
-            }?
+                // Newlines scroll the screen when the image ends at the
+                // bottom, unlike `CSI n B`.
+                _ => {
+                    counter.write_all(b"\r")?;
+                    counter.write_all(&b"\n".repeat(rows))?;
+                }
+            }
         }
-        write!(w, "{SIXEL_ESC}\\")?;
+        stats.emit_time = start.elapsed();
+        stats.output_bytes = counter.count;
+        Ok(stats)
+    }
+}
+
+impl<E: Quantizer> Backend for SixelEncoder<E> {
+    fn emit(&mut self, mut w: &mut dyn Write, img: &RgbImage) -> Result<()> {
+        let stats = self.encode(&mut w, img)?;
+        debug!("Encode stats: {stats:#?}");
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OctreeQuantizer, SixelDecoder};
+
+    const BLACK: Color = Rgb([0, 0, 0]);
+
+    #[test]
+    fn test_encode_to_string() {
+        let img = open_image(Path::new("assets/pure_red.png"), BLACK).unwrap();
+        let mut encoder = EncoderBuilder::<OctreeQuantizer>::new().build();
+        let (sixel, stats) = encoder.encode_to_string(&img).unwrap();
+        assert!(sixel.starts_with("\x1bPq\"1;1;300;300#0;2;100;0;0#0!300~-"));
+        assert!(sixel.ends_with("\x1b\\"));
+        assert_eq!(stats.output_bytes, sixel.len());
+        assert_eq!(stats.palette_size, 1);
+        assert_eq!(stats.band_count, 50);
+        assert_eq!(stats.color_switches, 50);
+    }
+
+    #[test]
+    fn test_encoder_reuse() {
+        let colors = open_image(Path::new("assets/colors.png"), BLACK).unwrap();
+        let rgb = open_image(Path::new("assets/rgb.png"), BLACK).unwrap();
+        let mut encoder = EncoderBuilder::<OctreeQuantizer>::new()
+            .palette_size(16)
+            .dither(true)
+            .build();
+        let (colors_sixel, _) = encoder.encode_to_vec(&colors).unwrap();
+        let (rgb_sixel, _) = encoder.encode_to_vec(&rgb).unwrap();
+        assert_eq!(encoder.encode_to_vec(&colors).unwrap().0, colors_sixel);
+
+        let mut fresh_encoder = EncoderBuilder::<OctreeQuantizer>::new()
+            .palette_size(16)
+            .dither(true)
+            .build();
+        assert_eq!(fresh_encoder.encode_to_vec(&rgb).unwrap().0, rgb_sixel);
+    }
+
+    #[test]
+    fn test_optimize_output_size() {
+        let mut encoder = EncoderBuilder::<OctreeQuantizer>::new().build();
+        let mut plain_encoder = EncoderBuilder::<OctreeQuantizer>::new()
+            .optimize(false)
+            .build();
+        let (mut total, mut plain_total) = (0, 0);
+        for img_path in [
+            "assets/colors.png",
+            "assets/cubes.png",
+            "assets/pure_red.png",
+            "assets/rgb.png",
+            "assets/rgb_and_white.png",
+            "assets/rika.png",
+            "assets/snake.png",
+            "assets/white_and_blue.png",
+        ] {
+            let img = open_image(Path::new(img_path), BLACK).unwrap();
+            let (sixel, stats) = encoder.encode_to_vec(&img).unwrap();
+            let (plain_sixel, plain_stats) = plain_encoder.encode_to_vec(&img).unwrap();
+            assert_eq!(
+                SixelDecoder::new().decode(&sixel).unwrap(),
+                SixelDecoder::new().decode(&plain_sixel).unwrap()
+            );
+            assert!(stats.output_bytes <= plain_stats.output_bytes);
+            assert_eq!(stats.color_switches, plain_stats.color_switches);
+            total += stats.output_bytes;
+            plain_total += plain_stats.output_bytes;
+        }
+        assert!(total < plain_total);
+    }
+
+    #[test]
+    fn test_encode_with_session() {
+        let img = open_image(Path::new("assets/rgb_and_white.png"), BLACK).unwrap();
+        let mut encoder = EncoderBuilder::<OctreeQuantizer>::new().build();
+        let mut session = Session::new();
+        let mut first = Vec::new();
+        encoder
+            .encode_with_session(&mut first, &img, &mut session)
+            .unwrap();
+        assert_eq!(first, encoder.encode_to_vec(&img).unwrap().0);
+
+        let mut second = Vec::new();
+        encoder
+            .encode_with_session(&mut second, &img, &mut session)
+            .unwrap();
+        assert!(second.len() < first.len());
+        assert!(!String::from_utf8(second).unwrap().contains(";2;"));
+
+        session.reset();
+        let mut third = Vec::new();
+        encoder
+            .encode_with_session(&mut third, &img, &mut session)
+            .unwrap();
+        assert_eq!(first, third);
+    }
+
+    #[test]
+    fn test_high_color() {
+        let img = RgbImage::from_fn(64, 64, |x, y| Rgb([x as u8 * 4, y as u8 * 4, 128]));
+        let mut encoder = EncoderBuilder::<OctreeQuantizer>::new().build();
+        let (_, stats) = encoder.encode_to_vec(&img).unwrap();
+        assert!(stats.palette_size <= MAX_COLORS);
+
+        let mut encoder = EncoderBuilder::<OctreeQuantizer>::new()
+            .palette_size(1024)
+            .registers(1024)
+            .build();
+        let (sixel, stats) = encoder.encode_to_string(&img).unwrap();
+        assert!(stats.palette_size > MAX_COLORS);
+        assert!(stats.palette_size <= 1024);
+        let last_register = stats.palette_size - 1;
+        assert!(sixel.contains(&format!("#{last_register};2;")));
+    }
+
+    #[test]
+    fn test_adaptive_palette() {
+        let img = RgbImage::from_fn(32, 64, |x, y| Rgb([x as u8 * 8, y as u8 * 4, 0]));
+        let mut encoder = EncoderBuilder::<OctreeQuantizer>::new()
+            .palette_size(16)
+            .adaptive_palette(2)
+            .build();
+        let (sixel, stats) = encoder.encode_to_string(&img).unwrap();
+        assert_eq!(stats.band_count, 11);
+        assert!(stats.palette_size <= 16);
+        assert_eq!(sixel.matches("#0;2;").count(), 6);
+        assert!(sixel.starts_with("\x1bPq\"1;1;32;64#0;2;"));
+    }
+
+    #[test]
+    fn test_introducer_and_terminator() {
+        let img = open_image(Path::new("assets/pure_red.png"), BLACK).unwrap();
+        let mut encoder = EncoderBuilder::<OctreeQuantizer>::new()
+            .introducer(Introducer::EightBit)
+            .build();
+        let (sixel, _) = encoder.encode_to_vec(&img).unwrap();
+        assert!(sixel.starts_with(b"\x90q\"1;1;300;300"));
+        assert!(sixel.ends_with(b"-\x9c"));
+        assert!(encoder.encode_to_string(&img).is_err());
+
+        let mut encoder = EncoderBuilder::<OctreeQuantizer>::new()
+            .terminator(Terminator::Bel)
+            .build();
+        let (sixel, _) = encoder.encode_to_string(&img).unwrap();
+        assert!(sixel.starts_with("\x1bPq"));
+        assert!(sixel.ends_with("-\x07"));
+    }
+
+    #[test]
+    fn test_pixel_aspect() {
+        let img = open_image(Path::new("assets/pure_red.png"), BLACK).unwrap();
+        let mut encoder = EncoderBuilder::<OctreeQuantizer>::new()
+            .pixel_aspect(2, 1)
+            .build();
+        let (sixel, stats) = encoder.encode_to_string(&img).unwrap();
+        assert!(sixel.starts_with("\x1bPq\"2;1;300;150#0;2;100;0;0"));
+        assert_eq!(stats.band_count, 25);
+
+        let mut encoder = EncoderBuilder::<OctreeQuantizer>::new()
+            .pixel_aspect(2, 1)
+            .aspect_macro(true)
+            .raster_attributes(false)
+            .build();
+        let (sixel, _) = encoder.encode_to_string(&img).unwrap();
+        assert!(sixel.starts_with("\x1bP0q#0;2;100;0;0"));
+    }
+
+    #[test]
+    fn test_printer() {
+        let img = open_image(Path::new("assets/pure_red.png"), BLACK).unwrap();
+        let mut encoder = EncoderBuilder::<OctreeQuantizer>::new()
+            .printer(PrinterOptions {
+                grid_size: 5,
+                pixel_doubling: true,
+                controller_mode: true,
+            })
+            .build();
+        let (sixel, stats) = encoder.encode_to_string(&img).unwrap();
+        assert!(sixel.starts_with("\x1b[5i\x1bP;;5q\"1;1;600;600#0;2;100;0;0#0!600~-"));
+        assert!(sixel.ends_with("\x1b\\\x1b[4i"));
+        assert_eq!(stats.band_count, 100);
+    }
+
+    #[test]
+    fn test_line_width() {
+        let img = open_image(Path::new("assets/rgb_and_white.png"), BLACK).unwrap();
+        let mut encoder = EncoderBuilder::<OctreeQuantizer>::new().build();
+        let (sixel, _) = encoder.encode_to_string(&img).unwrap();
+        let mut encoder = EncoderBuilder::<OctreeQuantizer>::new()
+            .line_width(40)
+            .build();
+        let (wrapped, _) = encoder.encode_to_string(&img).unwrap();
+        assert!(wrapped.lines().count() > 1);
+        assert!(wrapped.lines().all(|line| line.len() <= 40));
+        assert!(wrapped
+            .lines()
+            .all(|line| !line.ends_with('!') && !line.starts_with(|c: char| c.is_ascii_digit())));
+        assert_eq!(wrapped.replace('\n', ""), sixel);
+
+        // The limit holds for the escapes doubled by tmux and the chunks
+        // split by screen.
+        for passthrough in [Passthrough::Tmux, Passthrough::Screen] {
+            let mut encoder = EncoderBuilder::<OctreeQuantizer>::new()
+                .passthrough(passthrough)
+                .line_width(40)
+                .build();
+            let (wrapped, _) = encoder.encode_to_string(&img).unwrap();
+            assert!(wrapped.lines().count() > 1);
+            assert!(wrapped.lines().all(|line| line.len() <= 40));
+        }
+        let mut encoder = EncoderBuilder::<OctreeQuantizer>::new()
+            .passthrough(Passthrough::Tmux)
+            .build();
+        let (tmux, _) = encoder.encode_to_string(&img).unwrap();
+        let mut encoder = EncoderBuilder::<OctreeQuantizer>::new()
+            .passthrough(Passthrough::Tmux)
+            .line_width(40)
+            .build();
+        let (wrapped, _) = encoder.encode_to_string(&img).unwrap();
+        assert_eq!(wrapped.replace('\n', ""), tmux);
+    }
+
+    #[test]
+    fn test_cursor() {
+        let img = open_image(Path::new("assets/pure_red.png"), BLACK).unwrap();
+        let mut encoder = EncoderBuilder::<OctreeQuantizer>::new()
+            .cursor(CursorOptions {
+                save_restore: true,
+                position: Some((2, 10)),
+                move_below: false,
+                display_mode: true,
+                cell_height: 0,
+            })
+            .build();
+        let (sixel, _) = encoder.encode_to_string(&img).unwrap();
+        assert!(sixel.starts_with("\x1b7\x1b[2;10H\x1b[?80h\x1bPq\"1;1;300;300"));
+        assert!(sixel.ends_with("-\x1b\\\x1b[?80l\x1b8"));
+
+        // The restored cursor is at the top of the image, 300 pixels are 15
+        // rows of 20 pixel high cells.
+        let cursor = CursorOptions {
+            save_restore: true,
+            move_below: true,
+            cell_height: 20,
+            ..Default::default()
+        };
+        let mut encoder = EncoderBuilder::<OctreeQuantizer>::new()
+            .cursor(cursor)
+            .build();
+        let (sixel, _) = encoder.encode_to_string(&img).unwrap();
+        assert!(sixel.ends_with(&format!("\x1b8\r{}", "\n".repeat(15))));
+
+        // From a `position` the cursor is moved to the row below the image.
+        let mut encoder = EncoderBuilder::<OctreeQuantizer>::new()
+            .cursor(CursorOptions {
+                position: Some((2, 10)),
+                ..cursor
+            })
+            .build();
+        let (sixel, _) = encoder.encode_to_string(&img).unwrap();
+        assert!(sixel.ends_with("\x1b8\x1b[17;1H"));
+
+        // Without the cell height nothing is written.
+        let mut encoder = EncoderBuilder::<OctreeQuantizer>::new()
+            .cursor(CursorOptions {
+                cell_height: 0,
+                ..cursor
+            })
+            .build();
+        let mut out = Vec::new();
+        assert!(encoder.encode(&mut out, &img).is_err());
+        assert!(out.is_empty());
+
+        let mut encoder = EncoderBuilder::<OctreeQuantizer>::new()
+            .cursor(CursorOptions {
+                move_below: true,
+                ..Default::default()
+            })
+            .build();
+        let (sixel, _) = encoder.encode_to_string(&img).unwrap();
+        assert!(sixel.ends_with("\x1b\\\r\n"));
+    }
+
+    #[test]
+    fn test_fixed_palette() {
+        let img = open_image(Path::new("assets/pure_red.png"), BLACK).unwrap();
+        let mut encoder = EncoderBuilder::<OctreeQuantizer>::new()
+            .palette(&[Rgb([0, 0, 255]), Rgb([250, 0, 0]), Rgb([0, 255, 0])])
+            .registers(2)
+            .build();
+        let (sixel, stats) = encoder.encode_to_string(&img).unwrap();
+        assert!(sixel.starts_with("\x1bPq\"1;1;300;300#0;2;0;0;100#1;2;98;0;0#1!300~-"));
+        assert_eq!(stats.palette_size, 1);
+    }
+
+    #[test]
+    fn test_read_image() {
+        let file = File::open("assets/pure_red.png").unwrap();
+        let img = read_image(file, BLACK).unwrap();
+        assert_eq!(
+            img,
+            open_image(Path::new("assets/pure_red.png"), BLACK).unwrap()
+        );
+        assert!(read_image(&b"not an image"[..], BLACK).is_err());
+
+        // A 2x1 image with EXIF orientation 6 is shown rotated clockwise.
+        use image::{codecs::png::PngEncoder, ImageEncoder};
+        let exif = b"II*\0\x08\0\0\0\x01\0\x12\x01\x03\0\x01\0\0\0\x06\0\0\0\0\0\0\0";
+        let mut data = Vec::new();
+        let mut encoder = PngEncoder::new(&mut data);
+        encoder.set_exif_metadata(exif.to_vec()).unwrap();
+        encoder
+            .write_image(
+                &[255, 0, 0, 0, 0, 255],
+                2,
+                1,
+                image::ExtendedColorType::Rgb8,
+            )
+            .unwrap();
+        let img = read_image(&data[..], BLACK).unwrap();
+        assert_eq!(img.dimensions(), (1, 2));
+        assert_eq!(*img.get_pixel(0, 0), Rgb([255, 0, 0]));
+
+        // Transparent pixels are composited over the background.
+        let mut data = Vec::new();
+        PngEncoder::new(&mut data)
+            .write_image(
+                &[255, 0, 0, 255, 255, 0, 0, 128, 0, 0, 0, 0],
+                3,
+                1,
+                image::ExtendedColorType::Rgba8,
+            )
+            .unwrap();
+        let img = read_image(&data[..], Rgb([0, 0, 255])).unwrap();
+        assert_eq!(
+            img.pixels().copied().collect::<Vec<_>>(),
+            [Rgb([255, 0, 0]), Rgb([128, 0, 127]), Rgb([0, 0, 255]),]
+        );
+    }
+
+    #[test]
+    fn test_open_animation() {
+        use image::{codecs::gif::GifEncoder, Delay, Frame, Rgba, RgbaImage};
+
+        let path = std::env::temp_dir().join("rsixel_test_open_animation.gif");
+        let mut encoder = GifEncoder::new(File::create(&path).unwrap());
+        for color in [Rgba([255, 0, 0, 255]), Rgba([0, 0, 255, 255])] {
+            let frame = Frame::from_parts(
+                RgbaImage::from_pixel(4, 4, color),
+                0,
+                0,
+                Delay::from_numer_denom_ms(50, 1),
+            );
+            encoder.encode_frame(frame).unwrap();
+        }
+        drop(encoder);
+        let frames = open_animation(&path, BLACK).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].0.get_pixel(0, 0).0, [255, 0, 0]);
+        assert_eq!(frames[1].0.get_pixel(0, 0).0, [0, 0, 255]);
+        assert_eq!(frames[1].1, Duration::from_millis(50));
+
+        let frames = open_animation(Path::new("assets/pure_red.png"), BLACK).unwrap();
+        assert_eq!(frames.len(), 1);
+    }
+}
@@ -0,0 +1,190 @@
+use image::Rgb;
+use std::io;
+
+use crate::Color;
+
+/// `OSC 11` query of the default background color.
+const BACKGROUND_QUERY: &[u8] = b"\x1b]11;?\x1b\\";
+
+/// Primary Device Attributes query (`DA1`).
+const DEVICE_ATTRIBUTES_QUERY: &[u8] = b"\x1b[c";
+
+/// Maximum length of a report, longer answers are not what we asked for.
+#[cfg(unix)]
+const MAX_REPORT_LEN: usize = 64;
+
+/// Size of the terminal window that stdout is attached to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowSize {
+    pub columns: u16,
+    pub rows: u16,
+    /// Width in pixels, 0 when the terminal doesn't report it.
+    pub width: u16,
+    /// Height in pixels, 0 when the terminal doesn't report it.
+    pub height: u16,
+}
+
+impl WindowSize {
+    /// Query the window size, `None` when stdout is not a terminal.
+    #[cfg(unix)]
+    pub fn detect() -> Option<Self> {
+        let size = rustix::termios::tcgetwinsize(io::stdout()).ok()?;
+        (size.ws_col > 0).then_some(Self {
+            columns: size.ws_col,
+            rows: size.ws_row,
+            width: size.ws_xpixel,
+            height: size.ws_ypixel,
+        })
+    }
+
+    /// Query the window size, `None` when stdout is not a terminal.
+    #[cfg(not(unix))]
+    pub fn detect() -> Option<Self> {
+        let (width, height) = terminal_size::terminal_size_of(io::stdout())?;
+        Some(Self {
+            columns: width.0,
+            rows: height.0,
+            width: 0,
+            height: 0,
+        })
+    }
+
+    /// Width of a cell in pixels, if the terminal reports its pixel size.
+    pub fn cell_width(&self) -> Option<u16> {
+        (self.width > 0).then(|| self.width / self.columns.max(1))
+    }
+
+    /// Height of a cell in pixels, if the terminal reports its pixel size.
+    pub fn cell_height(&self) -> Option<u16> {
+        (self.height > 0).then(|| self.height / self.rows.max(1))
+    }
+}
+
+/// Write `request` to the controlling terminal and read its answer until
+/// `is_complete` accepts it. `None` when there is no controlling terminal or
+/// it doesn't answer within 0.1 seconds.
+#[cfg(unix)]
+fn query_terminal(request: &[u8], is_complete: impl Fn(&[u8]) -> bool) -> Option<Vec<u8>> {
+    use rustix::termios::{self, OptionalActions, SpecialCodeIndex};
+    use std::{
+        fs::OpenOptions,
+        io::{Read, Write},
+    };
+
+    let mut tty = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .ok()?;
+    let saved = termios::tcgetattr(&tty).ok()?;
+    let mut raw = saved.clone();
+    raw.make_raw();
+    // Reads return after 0.1 seconds without input.
+    raw.special_codes[SpecialCodeIndex::VMIN] = 0;
+    raw.special_codes[SpecialCodeIndex::VTIME] = 1;
+    termios::tcsetattr(&tty, OptionalActions::Now, &raw).ok()?;
+
+    let mut report = Vec::new();
+    let result = (|| -> io::Result<()> {
+        tty.write_all(request)?;
+        tty.flush()?;
+        let mut buf = [0; MAX_REPORT_LEN];
+        while report.len() < MAX_REPORT_LEN && !is_complete(&report) {
+            let n = tty.read(&mut buf[..MAX_REPORT_LEN - report.len()])?;
+            if n == 0 {
+                break;
+            }
+            report.extend_from_slice(&buf[..n]);
+        }
+        Ok(())
+    })();
+    termios::tcsetattr(&tty, OptionalActions::Now, &saved).ok()?;
+    result.ok()?;
+    (!report.is_empty()).then_some(report)
+}
+
+#[cfg(not(unix))]
+fn query_terminal(_request: &[u8], _is_complete: impl Fn(&[u8]) -> bool) -> Option<Vec<u8>> {
+    None
+}
+
+/// Ask the terminal for its background color with `OSC 11`. `None` when
+/// there is no controlling terminal or it doesn't answer.
+pub fn query_background() -> Option<Color> {
+    let report = query_terminal(BACKGROUND_QUERY, |report| {
+        report.ends_with(b"\x07") || report.ends_with(b"\x1b\\")
+    })?;
+    parse_color_report(&report)
+}
+
+/// Ask the terminal for its Primary Device Attributes (`DA1`), e.g. `4` is
+/// reported by terminals supporting sixel. `None` when there is no
+/// controlling terminal or it doesn't answer.
+pub fn query_device_attributes() -> Option<Vec<u32>> {
+    let report = query_terminal(DEVICE_ATTRIBUTES_QUERY, |report| report.ends_with(b"c"))?;
+    parse_device_attributes(&report)
+}
+
+/// Parse an `OSC 11;rgb:R/G/B` report, every component has one to four hex
+/// digits.
+fn parse_color_report(report: &[u8]) -> Option<Color> {
+    let report = std::str::from_utf8(report).ok()?;
+    let start = report.find("rgb:")? + "rgb:".len();
+    let end = report[start..]
+        .find(['\x07', '\x1b'])
+        .map_or(report.len(), |end| start + end);
+    let mut components = report[start..end].split('/').map(|component| {
+        if !(1..=4).contains(&component.len()) {
+            return None;
+        }
+        let max = (1 << (4 * component.len())) - 1;
+        let value = u32::from_str_radix(component, 16).ok()?;
+        Some(((value * 255 + max / 2) / max) as u8)
+    });
+    let color = Rgb([
+        components.next()??,
+        components.next()??,
+        components.next()??,
+    ]);
+    components.next().is_none().then_some(color)
+}
+
+/// Parse a `CSI ? Ps ; ... c` device attributes report.
+fn parse_device_attributes(report: &[u8]) -> Option<Vec<u32>> {
+    let report = std::str::from_utf8(report).ok()?;
+    let start = report.find("\x1b[?")? + "\x1b[?".len();
+    let end = start + report[start..].find('c')?;
+    report[start..end]
+        .split(';')
+        .map(|attribute| attribute.parse().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_color_report() {
+        assert_eq!(
+            parse_color_report(b"\x1b]11;rgb:ffff/8080/0000\x1b\\"),
+            Some(Rgb([255, 128, 0]))
+        );
+        assert_eq!(
+            parse_color_report(b"\x1b]11;rgb:f/80/000\x07"),
+            Some(Rgb([255, 128, 0]))
+        );
+        assert_eq!(parse_color_report(b"\x1b]11;rgb:ff/00\x07"), None);
+        assert_eq!(parse_color_report(b"\x1b[0n"), None);
+    }
+
+    #[test]
+    fn test_parse_device_attributes() {
+        assert_eq!(
+            parse_device_attributes(b"\x1b[?64;1;2;4;6;9c"),
+            Some(vec![64, 1, 2, 4, 6, 9])
+        );
+        assert_eq!(parse_device_attributes(b"\x1b[?1;2c"), Some(vec![1, 2]));
+        assert_eq!(parse_device_attributes(b"\x1b[?1;2"), None);
+    }
+}
@@ -0,0 +1,381 @@
+use anyhow::Result;
+use image::{
+    imageops::{self, dither, ColorMap, FilterType},
+    Rgb, RgbImage,
+};
+use std::{borrow::Cow, fmt::Write as _, io::Write, iter, marker::PhantomData};
+
+use crate::{Adjustments, Backend, Color, Palette, Quantizer, MAX_HIGH_COLORS};
+
+/// Unicode characters used to draw the pixels of a terminal cell.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum TextMode {
+    /// Upper and lower half blocks, 1x2 pixels per cell
+    #[default]
+    HalfBlock,
+    /// Quadrant blocks, 2x2 pixels per cell
+    Quadrant,
+    /// Sextant blocks from Symbols for Legacy Computing, 2x3 pixels per cell
+    Sextant,
+    /// Braille patterns, 2x4 pixels per cell
+    Braille,
+}
+
+const QUADRANTS: [char; 16] = [
+    ' ', '▘', '▝', '▀', '▖', '▌', '▞', '▛', '▗', '▚', '▐', '▜', '▄', '▙', '▟', '█',
+];
+
+/// Braille dot bit for every pixel of a 2x4 cell in row-major order.
+const BRAILLE_DOTS: [u8; 8] = [0x01, 0x08, 0x02, 0x10, 0x04, 0x20, 0x40, 0x80];
+
+impl TextMode {
+    /// Cell size in pixels.
+    fn cell_size(self) -> (u32, u32) {
+        match self {
+            TextMode::HalfBlock => (1, 2),
+            TextMode::Quadrant => (2, 2),
+            TextMode::Sextant => (2, 3),
+            TextMode::Braille => (2, 4),
+        }
+    }
+
+    /// Character drawing the pixels set in `mask`, bit `y * width + x`, in
+    /// the foreground color and the rest in the background color.
+    fn glyph(self, mask: u8) -> char {
+        match self {
+            TextMode::HalfBlock => [' ', '▀', '▄', '█'][mask as usize],
+            TextMode::Quadrant => QUADRANTS[mask as usize],
+            TextMode::Sextant => match mask {
+                0 => ' ',
+                21 => '▌',
+                42 => '▐',
+                63 => '█',
+                // U+1FB00 starts at mask 1 and skips the two half blocks.
+                _ => {
+                    let skipped = (mask > 21) as u32 + (mask > 42) as u32;
+                    char::from_u32(0x1FB00 + mask as u32 - 1 - skipped).unwrap()
+                }
+            },
+            TextMode::Braille => {
+                let dots = BRAILLE_DOTS
+                    .iter()
+                    .enumerate()
+                    .filter(|(bit, _)| mask & (1 << bit) != 0)
+                    .fold(0, |dots, (_, dot)| dots | *dot as u32);
+                char::from_u32(0x2800 + dots).unwrap()
+            }
+        }
+    }
+}
+
+/// SGR color space of the text output.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum TextColor {
+    /// 24-bit `38;2;R;G;B` colors
+    #[default]
+    #[cfg_attr(feature = "cli", value(name = "truecolor"))]
+    TrueColor,
+    /// xterm 256 color palette, the 16 theme dependent colors are not used
+    #[cfg_attr(feature = "cli", value(name = "256"))]
+    Ansi256,
+}
+
+/// The 6x6x6 color cube and the grayscale ramp of the xterm 256 color palette.
+fn xterm_palette() -> Palette {
+    const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    let mut palette = Palette::default();
+    for r in LEVELS {
+        for g in LEVELS {
+            for b in LEVELS {
+                palette.push(Rgb([r, g, b]));
+            }
+        }
+    }
+    for i in 0..24 {
+        let level = 8 + 10 * i;
+        palette.push(Rgb([level, level, level]));
+    }
+    palette
+}
+
+fn distance(a: Color, b: Color) -> u32 {
+    iter::zip(a.0, b.0)
+        .map(|(a, b)| {
+            let diff = a as i32 - b as i32;
+            (diff * diff) as u32
+        })
+        .sum()
+}
+
+fn mean(pixels: impl Iterator<Item = Color>) -> Color {
+    let (sum, count) = pixels.fold(([0u32; 3], 0), |(sum, count), pixel| {
+        (
+            [
+                sum[0] + pixel[0] as u32,
+                sum[1] + pixel[1] as u32,
+                sum[2] + pixel[2] as u32,
+            ],
+            count + 1,
+        )
+    });
+    Rgb(sum.map(|c| (c / count.max(1)) as u8))
+}
+
+/// Text backend drawing the image with Unicode block characters and SGR
+/// colors, for terminals without graphics support.
+pub struct TextEncoder<E: Quantizer> {
+    mode: TextMode,
+    color: TextColor,
+    palette_size: usize,
+    is_dither: bool,
+    columns: Option<u32>,
+    adjustments: Adjustments,
+    sgr: String,
+    _quantizer: PhantomData<E>,
+}
+
+impl<E: Quantizer> Default for TextEncoder<E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E: Quantizer> TextEncoder<E> {
+    pub fn new() -> Self {
+        Self {
+            mode: TextMode::default(),
+            color: TextColor::default(),
+            palette_size: 0,
+            is_dither: false,
+            columns: None,
+            adjustments: Adjustments::default(),
+            sgr: String::new(),
+            _quantizer: PhantomData,
+        }
+    }
+
+    pub fn mode(mut self, mode: TextMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn color(mut self, color: TextColor) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Quantize true color output to this many colors, 0 keeps the source
+    /// colors.
+    pub fn palette_size(mut self, palette_size: usize) -> Self {
+        self.palette_size = palette_size.min(MAX_HIGH_COLORS);
+        self
+    }
+
+    /// Dither the image to the palette, only used with a palette.
+    pub fn dither(mut self, is_dither: bool) -> Self {
+        self.is_dither = is_dither;
+        self
+    }
+
+    /// Scale the image down to at most this many cells wide.
+    pub fn columns(mut self, columns: u32) -> Self {
+        self.columns = Some(columns.max(1));
+        self
+    }
+
+    /// Correct the colors of the image before drawing it.
+    pub fn adjustments(mut self, adjustments: Adjustments) -> Self {
+        self.adjustments = adjustments;
+        self
+    }
+
+    /// Resize to the cell grid, cells are about twice as high as wide.
+    fn scale<'a>(&self, img: &'a RgbImage) -> Cow<'a, RgbImage> {
+        let (cell_width, cell_height) = self.mode.cell_size();
+        let mut width = img.width();
+        let mut height = (img.height() * cell_height).div_ceil(2 * cell_width);
+        if let Some(max_width) = self.columns.map(|columns| columns * cell_width) {
+            if width > max_width {
+                height = (height * max_width).div_ceil(width);
+                width = max_width;
+            }
+        }
+        if (width, height) == img.dimensions() {
+            Cow::Borrowed(img)
+        } else {
+            Cow::Owned(imageops::resize(
+                img,
+                width,
+                height.max(1),
+                FilterType::Triangle,
+            ))
+        }
+    }
+
+    /// Packed RGB value of `color`, or its palette index in 256 color mode.
+    fn resolve(&self, palette: Option<&Palette>, color: Color) -> u32 {
+        let color = match (palette, self.color) {
+            (Some(palette), TextColor::Ansi256) => return palette.index_of(&color) as u32 + 16,
+            (Some(palette), TextColor::TrueColor) => {
+                palette.get_palette()[palette.index_of(&color)]
+            }
+            (None, _) => color,
+        };
+        u32::from_be_bytes([0, color[0], color[1], color[2]])
+    }
+
+    fn push_sgr(&mut self, layer: u8, color: u32) {
+        if !self.sgr.is_empty() {
+            self.sgr.push(';');
+        }
+        match self.color {
+            TextColor::TrueColor => {
+                let [_, r, g, b] = color.to_be_bytes();
+                write!(self.sgr, "{layer};2;{r};{g};{b}")
+            }
+            TextColor::Ansi256 => write!(self.sgr, "{layer};5;{color}"),
+        }
+        .expect("writing to a string never fails");
+    }
+
+    pub fn encode<W: Write>(&mut self, w: &mut W, img: &RgbImage) -> Result<()> {
+        let scaled = self.adjustments.apply(self.scale(img));
+        let palette = match self.color {
+            TextColor::Ansi256 => Some(xterm_palette()),
+            TextColor::TrueColor if self.palette_size > 0 => {
+                Some(E::quantize(&scaled, self.palette_size))
+            }
+            TextColor::TrueColor => None,
+        };
+        let dithered;
+        let img: &RgbImage = match &palette {
+            Some(palette) if self.is_dither => {
+                let mut img = scaled.into_owned();
+                dither(&mut img, palette);
+                dithered = img;
+                &dithered
+            }
+            _ => &scaled,
+        };
+
+        let (cell_width, cell_height) = self.mode.cell_size();
+        let mut pixels = Vec::with_capacity((cell_width * cell_height) as usize);
+        for y in (0..img.height()).step_by(cell_height as usize) {
+            let (mut fg, mut bg) = (None, None);
+            for x in (0..img.width()).step_by(cell_width as usize) {
+                pixels.clear();
+                for dy in 0..cell_height.min(img.height() - y) {
+                    for dx in 0..cell_width.min(img.width() - x) {
+                        let bit = dy * cell_width + dx;
+                        pixels.push((bit, *img.get_pixel(x + dx, y + dy)));
+                    }
+                }
+                // Split the cell between its two most distant colors.
+                let (a, b) = (0..pixels.len())
+                    .flat_map(|i| (i + 1..pixels.len()).map(move |j| (i, j)))
+                    .max_by_key(|&(i, j)| distance(pixels[i].1, pixels[j].1))
+                    .map_or((pixels[0].1, pixels[0].1), |(i, j)| {
+                        (pixels[i].1, pixels[j].1)
+                    });
+                let is_fg = |pixel: Color| distance(pixel, a) <= distance(pixel, b);
+                let mask = pixels
+                    .iter()
+                    .filter(|(_, pixel)| is_fg(*pixel))
+                    .fold(0u8, |mask, (bit, _)| mask | 1 << bit);
+                let cell_fg = self.resolve(
+                    palette.as_ref(),
+                    mean(pixels.iter().map(|p| p.1).filter(|p| is_fg(*p))),
+                );
+                let cell_bg = self.resolve(
+                    palette.as_ref(),
+                    mean(pixels.iter().map(|p| p.1).filter(|p| !is_fg(*p))),
+                );
+
+                self.sgr.clear();
+                let glyph = if a == b || cell_fg == cell_bg {
+                    if bg != Some(cell_fg) {
+                        self.push_sgr(48, cell_fg);
+                        bg = Some(cell_fg);
+                    }
+                    ' '
+                } else {
+                    if fg != Some(cell_fg) {
+                        self.push_sgr(38, cell_fg);
+                        fg = Some(cell_fg);
+                    }
+                    if bg != Some(cell_bg) {
+                        self.push_sgr(48, cell_bg);
+                        bg = Some(cell_bg);
+                    }
+                    self.mode.glyph(mask)
+                };
+                if !self.sgr.is_empty() {
+                    write!(w, "\x1b[{}m", self.sgr)?;
+                }
+                write!(w, "{glyph}")?;
+            }
+            w.write_all(b"\x1b[0m\n")?;
+        }
+        Ok(())
+    }
+}
+
+impl<E: Quantizer> Backend for TextEncoder<E> {
+    fn emit(&mut self, mut w: &mut dyn Write, img: &RgbImage) -> Result<()> {
+        self.encode(&mut w, img)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OctreeQuantizer;
+
+    #[test]
+    fn test_glyphs() {
+        assert_eq!(TextMode::HalfBlock.glyph(0b01), '▀');
+        assert_eq!(TextMode::Quadrant.glyph(0b1001), '▚');
+        assert_eq!(TextMode::Sextant.glyph(1), '\u{1FB00}');
+        assert_eq!(TextMode::Sextant.glyph(21), '▌');
+        assert_eq!(TextMode::Sextant.glyph(62), '\u{1FB3B}');
+        assert_eq!(TextMode::Braille.glyph(0b0101_0101), '⡇');
+        assert_eq!(TextMode::Braille.glyph(0xff), '⣿');
+    }
+
+    #[test]
+    fn test_text_encoder() {
+        let mut img = RgbImage::from_pixel(2, 2, Rgb([255, 0, 0]));
+        img.put_pixel(0, 1, Rgb([0, 0, 255]));
+        img.put_pixel(1, 1, Rgb([0, 0, 255]));
+
+        let mut out = Vec::new();
+        let mut encoder = TextEncoder::<OctreeQuantizer>::new();
+        encoder.encode(&mut out, &img).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\x1b[38;2;255;0;0;48;2;0;0;255m▀▀\x1b[0m\n"
+        );
+
+        let mut out = Vec::new();
+        let mut encoder = TextEncoder::<OctreeQuantizer>::new().color(TextColor::Ansi256);
+        encoder.encode(&mut out, &img).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\x1b[38;5;196;48;5;21m▀▀\x1b[0m\n"
+        );
+
+        let mut out = Vec::new();
+        let mut encoder = TextEncoder::<OctreeQuantizer>::new()
+            .mode(TextMode::Quadrant)
+            .columns(1);
+        encoder
+            .encode(&mut out, &RgbImage::from_pixel(4, 4, Rgb([0, 255, 0])))
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\x1b[48;2;0;255;0m \x1b[0m\n"
+        );
+    }
+}
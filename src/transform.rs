@@ -0,0 +1,97 @@
+use anyhow::{bail, Result};
+use image::{imageops, RgbImage};
+
+/// Clockwise rotation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Rotation {
+    #[cfg_attr(feature = "cli", value(name = "90"))]
+    Rotate90,
+    #[cfg_attr(feature = "cli", value(name = "180"))]
+    Rotate180,
+    #[cfg_attr(feature = "cli", value(name = "270"))]
+    Rotate270,
+}
+
+/// Mirror axis.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Flip {
+    /// Mirror left to right
+    #[cfg_attr(feature = "cli", value(name = "h"))]
+    Horizontal,
+    /// Mirror top to bottom
+    #[cfg_attr(feature = "cli", value(name = "v"))]
+    Vertical,
+}
+
+/// Geometric changes applied to an image before it is quantized: first the
+/// crop, then the rotation and the flip last.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Transform {
+    /// Keep the `(x, y, width, height)` rectangle, clipped to the image.
+    pub crop: Option<(u32, u32, u32, u32)>,
+    pub rotate: Option<Rotation>,
+    pub flip: Option<Flip>,
+}
+
+impl Transform {
+    pub fn apply(&self, mut img: RgbImage) -> Result<RgbImage> {
+        if let Some((x, y, width, height)) = self.crop {
+            let (img_width, img_height) = img.dimensions();
+            if x >= img_width || y >= img_height || width == 0 || height == 0 {
+                bail!(
+                    "crop {x},{y},{width},{height} is outside the {img_width}x{img_height} image"
+                );
+            }
+            img = imageops::crop_imm(&img, x, y, width, height).to_image();
+        }
+        img = match self.rotate {
+            Some(Rotation::Rotate90) => imageops::rotate90(&img),
+            Some(Rotation::Rotate180) => imageops::rotate180(&img),
+            Some(Rotation::Rotate270) => imageops::rotate270(&img),
+            None => img,
+        };
+        img = match self.flip {
+            Some(Flip::Horizontal) => imageops::flip_horizontal(&img),
+            Some(Flip::Vertical) => imageops::flip_vertical(&img),
+            None => img,
+        };
+        Ok(img)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    #[test]
+    fn test_transform() {
+        let img = RgbImage::from_fn(4, 3, |x, y| Rgb([x as u8, y as u8, 0]));
+        let crop = Transform {
+            crop: Some((1, 1, 10, 1)),
+            ..Default::default()
+        };
+        let cropped = crop.apply(img.clone()).unwrap();
+        assert_eq!(cropped.dimensions(), (3, 1));
+        assert_eq!(*cropped.get_pixel(0, 0), Rgb([1, 1, 0]));
+
+        let rotate = Transform {
+            rotate: Some(Rotation::Rotate90),
+            flip: Some(Flip::Horizontal),
+            ..Default::default()
+        };
+        let rotated = rotate.apply(img.clone()).unwrap();
+        assert_eq!(rotated.dimensions(), (3, 4));
+        // Rotated clockwise the bottom left corner is at the top left, the
+        // flip moves it to the top right.
+        assert_eq!(*rotated.get_pixel(2, 0), Rgb([0, 2, 0]));
+
+        let outside = Transform {
+            crop: Some((4, 0, 1, 1)),
+            ..Default::default()
+        };
+        assert!(outside.apply(img).is_err());
+    }
+}